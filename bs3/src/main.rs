@@ -1,4 +1,6 @@
 use bs3_core::browser_sync::BrowserSync;
+use bs3_core::cli::eval::EvalCmd;
+use bs3_core::cli::navigate::NavigateCmd;
 use bs3_core::start;
 use bs3_core::start::Final;
 use std::process::exit;
@@ -7,7 +9,26 @@ use tokio::sync::{broadcast, oneshot};
 #[actix_web::main]
 async fn main() {
     env_logger::init();
-    let browser_sync = BrowserSync::try_from_args(std::env::args().skip(1));
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let subcommand = match args.first().map(String::as_str) {
+        Some("eval") => Some(match EvalCmd::try_from_args(args.iter().skip(1)) {
+            Ok(cmd) => cmd.run().await,
+            Err(e) => Err(e),
+        }),
+        Some("navigate") => Some(match NavigateCmd::try_from_args(args.iter().skip(1)) {
+            Ok(cmd) => cmd.run().await,
+            Err(e) => Err(e),
+        }),
+        _ => None,
+    };
+    if let Some(result) = subcommand {
+        if let Err(e) = result {
+            eprintln!("error: {}", e);
+            exit(1);
+        }
+        exit(0);
+    }
+    let browser_sync = BrowserSync::try_from_args(args.into_iter());
     match browser_sync {
         Ok(browser_sync) => {
             log::debug!("{:#?}", browser_sync);
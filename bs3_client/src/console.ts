@@ -0,0 +1,47 @@
+import {Transport} from "./transport";
+import {LogLevel} from "../../bs3_core/pkg/bs3_core";
+
+const LEVELS: LogLevel[] = ["debug", "log", "info", "warn", "error"];
+
+/**
+ * Forward `console.*` calls, uncaught errors and unhandled rejections
+ * to the bs3 terminal output
+ */
+export function forwardConsole(ws: Transport) {
+    const send = (level: LogLevel, args: any[], stack: string | null, url: string | null) => {
+        ws.next({kind: "Log", payload: {level, args: args.map(stringify), stack, url}});
+    };
+
+    LEVELS.forEach(level => {
+        const original = console[level];
+        console[level] = function (...args: any[]) {
+            original.apply(console, args);
+            try {
+                send(level, args, null, null);
+            } catch (e) {
+                // never let forwarding break the page
+            }
+        };
+    });
+
+    window.addEventListener('error', (evt: ErrorEvent) => {
+        const url = evt.filename ? evt.filename + ':' + evt.lineno + ':' + evt.colno : null;
+        send("error", [evt.message], evt.error && evt.error.stack || null, url);
+    });
+
+    window.addEventListener('unhandledrejection', (evt: PromiseRejectionEvent) => {
+        const reason = evt.reason;
+        send("error", ['Unhandled rejection:', reason], reason && reason.stack || null, null);
+    });
+}
+
+export function stringify(arg: any): string {
+    if (typeof arg === 'string') return arg;
+    if (arg instanceof Error) return arg.name + ': ' + arg.message;
+    try {
+        const json = JSON.stringify(arg);
+        return json === undefined ? String(arg) : json;
+    } catch (e) {
+        return String(arg);
+    }
+}
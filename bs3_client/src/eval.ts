@@ -0,0 +1,25 @@
+import {Transport} from "./transport";
+import {filter} from "rxjs/operators";
+import {Subscription} from "rxjs";
+import {stringify} from "./console";
+
+/**
+ * Run code sent by the server (only when bs3 was started with `--allow-eval`)
+ * and reply with the result. Promises are awaited before replying
+ */
+export function remoteEval(ws: Transport): Subscription {
+    return ws.pipe(
+        filter(msg => msg.kind === "Eval"),
+    ).subscribe(async (msg) => {
+        if (msg.kind !== "Eval") return;
+        const {id, code} = msg.payload;
+        try {
+            // indirect eval, so the code runs in the global scope
+            const value = await (0, eval)(code);
+            ws.next({kind: "EvalResult", payload: {id, value: value === undefined ? null : stringify(value), error: null}});
+        } catch (e) {
+            const error = e instanceof Error ? e.name + ': ' + e.message : stringify(e);
+            ws.next({kind: "EvalResult", payload: {id, value: null, error}});
+        }
+    });
+}
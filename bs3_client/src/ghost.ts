@@ -0,0 +1,227 @@
+import {Transport} from "./transport";
+import {ClientMsg} from "../../bs3_core/pkg/bs3_core";
+import {fromEvent, merge, Observable, Subscription} from "rxjs";
+import {filter, map, throttleTime} from "rxjs/operators";
+
+/**
+ * Set while a remote event is being applied, so that it isn't sent straight back
+ */
+let applying = false;
+
+/**
+ * Mirror scroll, clicks and form interactions to/from every other connected browser.
+ *
+ * The server decides which types are enabled, and drops echoes
+ */
+export function ghostMode(ws: Transport): Subscription {
+    const outgoing = merge(scroll(), clicks(), location(), inputs(), toggles(), submits()).pipe(
+        filter(() => !applying),
+    );
+    const sub = new Subscription();
+    sub.add(outgoing.subscribe(msg => ws.next(msg)));
+    sub.add(ws.subscribe(msg => withApplying(() => apply(msg))));
+    return sub;
+}
+
+function scroll(): Observable<ClientMsg> {
+    return fromEvent(window, 'scroll').pipe(
+        throttleTime(50, undefined, {leading: true, trailing: true}),
+        map((): ClientMsg => {
+            const el = document.scrollingElement || document.documentElement;
+            return {
+                kind: "Scroll",
+                payload: {
+                    x: proportion(el.scrollLeft, el.scrollWidth - el.clientWidth),
+                    y: proportion(el.scrollTop, el.scrollHeight - el.clientHeight),
+                }
+            }
+        }),
+    );
+}
+
+/**
+ * Clicks on a form's submit button aren't sent, the `Submit` that follows is
+ */
+function clicks(): Observable<ClientMsg> {
+    return fromEvent<MouseEvent>(document, 'click', {capture: true}).pipe(
+        filter(evt => evt.target instanceof Element
+            && !isToggle(evt.target)
+            && !linkFor(evt.target)
+            && !submitButtonFor(evt.target)),
+        map((evt): ClientMsg => ({kind: "Click", payload: {selector: selectorFor(evt.target as Element)}})),
+    );
+}
+
+/**
+ * Link clicks, `history.pushState` and the back/forward buttons
+ */
+function location(): Observable<ClientMsg> {
+    const links = fromEvent<MouseEvent>(document, 'click', {capture: true}).pipe(
+        map(evt => evt.target instanceof Element ? linkFor(evt.target) : undefined),
+        filter((link): link is HTMLAnchorElement => Boolean(link)),
+        map(link => navigateMsg(link.href)),
+    );
+    const pushes = new Observable<ClientMsg>(subscriber => {
+        const original = history.pushState;
+        history.pushState = function (data: any, title: string, url?: string | null) {
+            original.call(history, data, title, url);
+            subscriber.next(navigateMsg(window.location.href));
+        };
+        return () => {
+            history.pushState = original;
+        };
+    });
+    const pops = fromEvent(window, 'popstate').pipe(
+        map(() => navigateMsg(window.location.href)),
+    );
+    return merge(links, pushes, pops);
+}
+
+function navigateMsg(href: string): ClientMsg {
+    const url = new URL(href, window.location.href);
+    return {
+        kind: "Navigate",
+        payload: {
+            path: url.pathname,
+            query: url.search ? url.search.slice(1) : null,
+            hash: url.hash ? url.hash.slice(1) : null,
+        }
+    };
+}
+
+/**
+ * A same-origin link that will navigate the current tab
+ */
+function linkFor(el: Element): HTMLAnchorElement | undefined {
+    const link = el.closest('a[href]');
+    if (!(link instanceof HTMLAnchorElement)) return undefined;
+    if (link.target && link.target !== '_self') return undefined;
+    if (link.hasAttribute('download')) return undefined;
+    if (link.origin !== window.location.origin) return undefined;
+    return link;
+}
+
+function inputs(): Observable<ClientMsg> {
+    return merge(
+        fromEvent(document, 'input', {capture: true}),
+        fromEvent(document, 'change', {capture: true}),
+    ).pipe(
+        map(evt => evt.target),
+        filter((target): target is HTMLInputElement | HTMLTextAreaElement | HTMLSelectElement =>
+            (target instanceof HTMLInputElement && !isToggle(target))
+            || target instanceof HTMLTextAreaElement
+            || target instanceof HTMLSelectElement
+        ),
+        map((target): ClientMsg => ({kind: "Input", payload: {selector: selectorFor(target), value: target.value}})),
+    );
+}
+
+function toggles(): Observable<ClientMsg> {
+    return fromEvent(document, 'change', {capture: true}).pipe(
+        map(evt => evt.target),
+        filter((target): target is HTMLInputElement => target instanceof Element && isToggle(target)),
+        map((target): ClientMsg => ({kind: "Toggle", payload: {selector: selectorFor(target), checked: target.checked}})),
+    );
+}
+
+function submits(): Observable<ClientMsg> {
+    return fromEvent(document, 'submit', {capture: true}).pipe(
+        filter(evt => evt.target instanceof HTMLFormElement),
+        map((evt): ClientMsg => ({kind: "Submit", payload: {selector: selectorFor(evt.target as Element)}})),
+    );
+}
+
+function apply(msg: ClientMsg) {
+    switch (msg.kind) {
+        case "Scroll": {
+            const el = document.scrollingElement || document.documentElement;
+            window.scrollTo(
+                msg.payload.x * (el.scrollWidth - el.clientWidth),
+                msg.payload.y * (el.scrollHeight - el.clientHeight),
+            );
+            break;
+        }
+        case "Click": {
+            const el = document.querySelector<HTMLElement>(msg.payload.selector);
+            if (el) el.click();
+            break;
+        }
+        case "Navigate": {
+            const {path, query, hash} = msg.payload;
+            const next = path + (query ? '?' + query : '') + (hash ? '#' + hash : '');
+            if (next !== window.location.pathname + window.location.search + window.location.hash) {
+                window.location.assign(next);
+            }
+            break;
+        }
+        case "Input": {
+            const el = document.querySelector<HTMLInputElement>(msg.payload.selector);
+            if (el) el.value = msg.payload.value;
+            break;
+        }
+        case "Toggle": {
+            const el = document.querySelector<HTMLInputElement>(msg.payload.selector);
+            if (el) el.checked = msg.payload.checked;
+            break;
+        }
+        case "Submit": {
+            // requestSubmit runs the page's own validation & submit handlers, submit() skips them
+            const el = document.querySelector<HTMLFormElement>(msg.payload.selector);
+            if (el && typeof el.requestSubmit === 'function') el.requestSubmit();
+            else if (el) el.submit();
+            break;
+        }
+    }
+}
+
+function withApplying(fn: () => void) {
+    applying = true;
+    try {
+        fn();
+    } finally {
+        applying = false;
+    }
+}
+
+function isToggle(el: Element): boolean {
+    return el instanceof HTMLInputElement && (el.type === 'checkbox' || el.type === 'radio');
+}
+
+/**
+ * A button that submits the form it belongs to
+ */
+function submitButtonFor(el: Element): HTMLButtonElement | HTMLInputElement | undefined {
+    const button = el.closest('button, input');
+    if (button instanceof HTMLButtonElement && button.type === 'submit' && button.form) return button;
+    if (button instanceof HTMLInputElement && (button.type === 'submit' || button.type === 'image') && button.form) {
+        return button;
+    }
+    return undefined;
+}
+
+function proportion(value: number, max: number): number {
+    return max > 0 ? value / max : 0;
+}
+
+/**
+ * A selector that identifies `el` on every device showing the same page
+ */
+export function selectorFor(el: Element): string {
+    const parts: string[] = [];
+    let node: Element | null = el;
+    while (node && node !== document.documentElement) {
+        if (node.id) {
+            parts.unshift('#' + CSS.escape(node.id));
+            break;
+        }
+        let index = 1;
+        let sibling = node.previousElementSibling;
+        while (sibling) {
+            if (sibling.tagName === node.tagName) index += 1;
+            sibling = sibling.previousElementSibling;
+        }
+        parts.unshift(node.tagName.toLowerCase() + ':nth-of-type(' + index + ')');
+        node = node.parentElement;
+    }
+    return parts.join(' > ');
+}
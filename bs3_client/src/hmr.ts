@@ -0,0 +1,123 @@
+import {Transport} from "./transport";
+import {filter} from "rxjs/operators";
+import {Subscription} from "rxjs";
+import {notify} from "./notify";
+
+type AcceptCallback = (mod: any) => void;
+
+interface Accept {
+    /**
+     * null when the module accepts itself
+     */
+    deps: string[] | null;
+    /**
+     * `accept("./a.js", cb)` receives the module, `accept(["./a.js"], cb)` an array
+     */
+    single: boolean;
+    callback?: AcceptCallback;
+}
+
+interface HotModule {
+    accepts: Accept[];
+    disposers: Array<(data: any) => void>;
+    data: any;
+}
+
+/**
+ * The hot context given to each ES module, following the `import.meta.hot` conventions
+ */
+export interface Hot {
+    data: any;
+    accept(deps?: string | string[] | AcceptCallback, callback?: AcceptCallback): void;
+    dispose(callback: (data: any) => void): void;
+    invalidate(): void;
+}
+
+/**
+ * Keyed by pathname, since updated modules are re-imported with a `?t=` param
+ */
+const modules = new Map<string, HotModule>();
+
+/**
+ * Native `import()`, the bundle is compiled to commonjs which would turn it into `require()`.
+ * Created on the first update only, since pages with a Content-Security-Policy
+ * need `'unsafe-eval'` for it, which bs3 only adds with `--hmr`
+ */
+let importFresh: ((url: string) => Promise<any>) | undefined;
+
+/**
+ * Apply `HmrUpdate` messages, and provide the `window.__bs3_hmr.hot()` that
+ * bs3 calls at the top of each module served with `--hmr`
+ */
+export function hmr(ws: Transport): Subscription {
+    (window as any).__bs3_hmr = {hot: (url: string) => hot(ws, url)};
+    return ws.pipe(
+        filter(msg => msg.kind === "HmrUpdate"),
+    ).subscribe((msg) => {
+        if (msg.kind !== "HmrUpdate") return;
+        const {modules: paths, timestamp} = msg.payload;
+        paths.forEach(path => {
+            update(path, timestamp).catch((e) => {
+                console.error('[bs3] hot update of %s failed, reloading', path, e);
+                window.location.reload();
+            });
+        });
+    });
+}
+
+function hot(ws: Transport, url: string): Hot {
+    const module = new URL(url).pathname;
+    // a re-imported module registers again, keeping only what `dispose` handed over
+    const previous = modules.get(module);
+    const record: HotModule = {accepts: [], disposers: [], data: previous ? previous.data : {}};
+    modules.set(module, record);
+    return {
+        data: record.data,
+        accept(deps, callback) {
+            if (deps === undefined || typeof deps === 'function') {
+                record.accepts.push({deps: null, single: true, callback: deps});
+                ws.next({kind: "HmrAccept", payload: {module, deps: []}});
+                return;
+            }
+            const list = (Array.isArray(deps) ? deps : [deps]).map(dep => new URL(dep, url).pathname);
+            record.accepts.push({deps: list, single: !Array.isArray(deps), callback});
+            ws.next({kind: "HmrAccept", payload: {module, deps: list}});
+        },
+        dispose(callback) {
+            record.disposers.push(callback);
+        },
+        invalidate() {
+            window.location.reload();
+        },
+    };
+}
+
+/**
+ * Dispose the old version of `path`, import the new one, then hand it to
+ * whichever modules accepted it
+ */
+async function update(path: string, timestamp: number) {
+    const old = modules.get(path);
+    if (old) {
+        const data = {};
+        old.disposers.forEach(dispose => dispose(data));
+        old.data = data;
+    }
+    if (!importFresh) {
+        importFresh = new Function('url', 'return import(url)') as (url: string) => Promise<any>;
+    }
+    const mod = await importFresh(path + '?t=' + timestamp);
+    if (old) {
+        old.accepts
+            .filter(accept => accept.deps === null)
+            .forEach(accept => accept.callback && accept.callback(mod));
+    }
+    modules.forEach((record) => {
+        record.accepts.forEach(accept => {
+            if (!accept.deps || accept.deps.indexOf(path) === -1 || !accept.callback) return;
+            const deps = accept.deps;
+            accept.callback(accept.single ? mod : deps.map(dep => dep === path ? mod : undefined));
+        });
+    });
+    notify('[bs3] hot updated ' + path);
+}
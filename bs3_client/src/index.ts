@@ -1,49 +1,192 @@
-import {webSocket} from "rxjs/webSocket";
 import {ClientMsg} from "../../bs3_core/pkg/bs3_core";
-import {buffer, debounceTime, filter, share, switchMap} from "rxjs/operators";
-import {EMPTY, Observable, of} from "rxjs";
-
-const wsUri = (window.location.protocol == 'https:' && 'wss://' || 'ws://') + window.location.host + '/__bs3/ws/';
-const ws = webSocket<ClientMsg>(wsUri);
-
-const fs = ws.pipe(
-    filter(x => x.kind === "FsNotify"),
-) as Observable<Evt<"FsNotify">>;
-
-const fsSub = fs.pipe(
-    filter(x => !x.payload.item.path.match(/.map$/)),
-    buffer(fs.pipe(debounceTime(500))),
-    share(),
-);
-
-const inject = [
-    /.css$/,
-    /.jpg$/,
-    /.png$/,
-];
-
-type Effects =
-    | {
-        kind: 'Reload'
-    };
+import {debounceTime, filter, map, startWith} from "rxjs/operators";
+import {fromEvent, Observable} from "rxjs";
+import {ghostMode} from "./ghost";
+import {forwardConsole} from "./console";
+import {remoteEval} from "./eval";
+import {hmr} from "./hmr";
+import {clearOverlay, notify, showOverlay} from "./notify";
+import {connect} from "./transport";
+
+const ws = connect(() => waitForServer());
 
-const actions = fsSub.pipe(switchMap((events): Observable<Effects> => {
-    if (events.every((evt) => inject.some(regex => evt.payload.item.path.match(regex)))) {
-        console.log('all were injectable');
-        return EMPTY;
+/**
+ * Messages that should be shown once the page has reloaded
+ */
+const PENDING_KEY = '__bs3_pending_notify';
+
+const pending = sessionStorage.getItem(PENDING_KEY);
+if (pending) {
+    sessionStorage.removeItem(PENDING_KEY);
+    if (document.readyState === 'loading') {
+        window.addEventListener('DOMContentLoaded', () => notify(pending));
     } else {
-        return of({kind: "Reload"});
+        notify(pending);
     }
-})) as Observable<Effects>;
+}
 
-const sub = actions.subscribe((action) => {
-    switch (action.kind) {
-        case "Reload": {
-            window.location.reload()
+/**
+ * Must match `PROTOCOL_VERSION` in bs3_core/src/ws/client.rs
+ */
+const PROTOCOL_VERSION = 1;
+const FEATURES = ["ghost", "console", "eval", "overlay", "hmr"];
+
+/**
+ * Captured now, since `currentScript` is only set while the bundle first executes
+ */
+const bundleSrc = (document.currentScript as HTMLScriptElement | null)?.src;
+
+/**
+ * Let the server know which page we're on, so that we're grouped
+ * with other browsers showing the same page
+ */
+ws.next({
+    kind: "Hello",
+    payload: {
+        url: window.location.href,
+        user_agent: navigator.userAgent,
+        version: PROTOCOL_VERSION,
+        features: FEATURES,
+        relay_token: null,
+    }
+});
+
+const viewportSub = fromEvent(window, 'resize').pipe(
+    debounceTime(500),
+    startWith(null),
+    map((): ClientMsg => ({kind: "Viewport", payload: {width: window.innerWidth, height: window.innerHeight}})),
+).subscribe(msg => ws.next(msg));
+
+/**
+ * The server decides whether a change is injected, reloaded or ignored,
+ * the client just carries out the instruction
+ */
+const reload = ws.pipe(
+    filter(x => x.kind === "Reload"),
+    debounceTime(500),
+) as Observable<Evt<"Reload">>;
+
+const sub = ws.subscribe((msg) => {
+    switch (msg.kind) {
+        case "InjectCss": {
+            injectCss(msg.payload.web_path);
+            notify('[bs3] injected ' + msg.payload.web_path);
+            break;
+        }
+        case "ReplaceImage": {
+            replaceImage(msg.payload.web_path);
+            notify('[bs3] replaced ' + msg.payload.web_path);
+            break;
+        }
+        case "Notify": {
+            notify(msg.payload.message, msg.payload.timeout);
+            break;
+        }
+        case "ErrorOverlay": {
+            showOverlay(msg.payload);
+            break;
+        }
+        case "ClearOverlay": {
+            clearOverlay();
+            break;
+        }
+        case "Welcome": {
+            console.debug('[bs3] connected, protocol v%d, features: %s', msg.payload.version, msg.payload.features.join(', '));
+            break;
+        }
+        case "HardReload": {
+            hardReload(msg.payload.reason);
+            break;
         }
     }
 });
 
+const ghostSub = ghostMode(ws);
+
+forwardConsole(ws);
+
+const evalSub = remoteEval(ws);
+
+const hmrSub = hmr(ws);
+
+const reloadSub = reload.subscribe((msg) => {
+    console.log('[bs3] reloading: %s', msg.payload.reason);
+    sessionStorage.setItem(PENDING_KEY, '[bs3] reloaded: ' + msg.payload.reason);
+    window.location.reload();
+});
+
+const HARD_RELOAD_KEY = '__bs3_hard_reload';
+
+/**
+ * Re-fetch this bundle bypassing the HTTP cache, then reload the page.
+ * If that already happened moments ago, the fresh bundle didn't help -
+ * show why instead of reloading forever
+ */
+function hardReload(reason: string) {
+    const previous = Number(sessionStorage.getItem(HARD_RELOAD_KEY));
+    if (previous && Date.now() - previous < 10000) {
+        showOverlay({
+            title: 'bs3 client is out of date',
+            body: reason + '\n\nA hard reload did not fix it, try clearing the browser cache.',
+            file: bundleSrc || null,
+            line: null,
+        });
+        return;
+    }
+    sessionStorage.setItem(HARD_RELOAD_KEY, String(Date.now()));
+    sessionStorage.setItem(PENDING_KEY, '[bs3] client updated');
+    const refetch = bundleSrc ? fetch(bundleSrc, {cache: 'reload'}) : Promise.resolve();
+    refetch.catch(() => undefined).then(() => window.location.reload());
+}
+
+/**
+ * When the connection drops, keep trying the server and reload
+ * as soon as it's back - it may have restarted with new config
+ */
+function waitForServer() {
+    notify('[bs3] disconnected, waiting for the server...', 0);
+    // a plain request, since the WebSocket upgrade may be what's blocked
+    const retry = () => {
+        fetch(window.location.href, {method: 'HEAD', cache: 'no-store'})
+            .then(() => {
+                sessionStorage.setItem(PENDING_KEY, '[bs3] reconnected');
+                window.location.reload();
+            })
+            .catch(() => setTimeout(retry, 1000));
+    };
+    setTimeout(retry, 1000);
+}
+
+function injectCss(webPath: string) {
+    const links = document.querySelectorAll<HTMLLinkElement>('link[rel="stylesheet"]');
+    for (let i = 0; i < links.length; i++) {
+        const next = cacheBust(links[i].href, webPath);
+        if (next) {
+            links[i].href = next;
+        }
+    }
+}
+
+function replaceImage(webPath: string) {
+    const images = document.querySelectorAll<HTMLImageElement>('img');
+    for (let i = 0; i < images.length; i++) {
+        const next = cacheBust(images[i].src, webPath);
+        if (next) {
+            images[i].src = next;
+        }
+    }
+}
+
+/**
+ * If `href` points to `webPath`, return it with a cache-busting param
+ */
+function cacheBust(href: string, webPath: string): string | undefined {
+    if (!href) return undefined;
+    const url = new URL(href, window.location.href);
+    if (url.host !== window.location.host || url.pathname !== webPath) return undefined;
+    url.searchParams.set('bs3', String(Date.now()));
+    return url.toString();
+}
 
 /**
  * Create a 'helper' type for 'extracting' ONE of the union's members
@@ -0,0 +1,99 @@
+import {ErrorOverlayMsg} from "../../bs3_core/pkg/bs3_core";
+
+const DEFAULT_TIMEOUT = 2000;
+const TOAST_ID = '__bs3_toast';
+const OVERLAY_ID = '__bs3_overlay';
+
+/**
+ * Show a short message in the corner of the page. Any previous toast is
+ * replaced. A `timeout` of 0 keeps it on screen until the next one
+ */
+export function notify(message: string, timeout: number | null = DEFAULT_TIMEOUT) {
+    const existing = document.getElementById(TOAST_ID);
+    if (existing) existing.remove();
+
+    const toast = document.createElement('div');
+    toast.id = TOAST_ID;
+    toast.textContent = message;
+    Object.assign(toast.style, {
+        position: 'fixed',
+        top: '0',
+        right: '0',
+        zIndex: '2147483647',
+        padding: '12px 16px',
+        background: '#1e1e1e',
+        color: '#fff',
+        font: '14px/1.4 sans-serif',
+        borderBottomLeftRadius: '5px',
+        pointerEvents: 'none',
+    });
+    document.body.appendChild(toast);
+
+    const ms = timeout === null ? DEFAULT_TIMEOUT : timeout;
+    if (ms > 0) {
+        setTimeout(() => toast.remove(), ms);
+    }
+}
+
+/**
+ * Cover the whole page with an error, eg: a failed build or an unreachable proxy target
+ */
+export function showOverlay(msg: ErrorOverlayMsg) {
+    clearOverlay();
+
+    const overlay = document.createElement('div');
+    overlay.id = OVERLAY_ID;
+    Object.assign(overlay.style, {
+        position: 'fixed',
+        top: '0',
+        left: '0',
+        right: '0',
+        bottom: '0',
+        zIndex: '2147483646',
+        overflow: 'auto',
+        padding: '32px',
+        background: 'rgba(0, 0, 0, 0.9)',
+        color: '#e8e8e8',
+        font: '14px/1.5 monospace',
+    });
+
+    const title = document.createElement('h2');
+    title.textContent = msg.title;
+    Object.assign(title.style, {color: '#ff5555', margin: '0 0 16px'});
+    overlay.appendChild(title);
+
+    if (msg.file) {
+        const file = document.createElement('div');
+        file.textContent = msg.line !== null ? msg.file + ':' + msg.line : msg.file;
+        Object.assign(file.style, {color: '#8ab4f8', marginBottom: '16px'});
+        overlay.appendChild(file);
+    }
+
+    const body = document.createElement('pre');
+    body.textContent = msg.body;
+    Object.assign(body.style, {whiteSpace: 'pre-wrap', margin: '0'});
+    overlay.appendChild(body);
+
+    const close = document.createElement('button');
+    close.textContent = '×';
+    close.title = 'Dismiss';
+    Object.assign(close.style, {
+        position: 'absolute',
+        top: '16px',
+        right: '16px',
+        background: 'none',
+        border: 'none',
+        color: '#e8e8e8',
+        fontSize: '24px',
+        cursor: 'pointer',
+    });
+    close.addEventListener('click', clearOverlay);
+    overlay.appendChild(close);
+
+    document.body.appendChild(overlay);
+}
+
+export function clearOverlay() {
+    const existing = document.getElementById(OVERLAY_ID);
+    if (existing) existing.remove();
+}
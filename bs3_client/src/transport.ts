@@ -0,0 +1,97 @@
+import {webSocket} from "rxjs/webSocket";
+import {Subject} from "rxjs";
+import {ClientMsg} from "../../bs3_core/pkg/bs3_core";
+
+/**
+ * Messages in both directions: `next()` sends to the server,
+ * subscribers receive everything the server sends
+ */
+export type Transport = Subject<ClientMsg>;
+
+const wsUri = (window.location.protocol == 'https:' && 'wss://' || 'ws://') + window.location.host + '/__bs3/ws/';
+const SSE_PATH = '/__bs3/sse';
+const MSG_PATH = '/__bs3/msg';
+
+/**
+ * Connect over WebSocket, falling back to Server-Sent Events + POST when the
+ * upgrade is blocked (eg: by a corporate proxy). Add `bs3-transport=sse` to the
+ * page's query to skip the WebSocket attempt.
+ *
+ * Outgoing messages are buffered until one of them has connected.
+ * `onClose` is called if an established connection drops
+ */
+export function connect(onClose: () => void): Transport {
+    const incoming = new Subject<ClientMsg>();
+    const pending: ClientMsg[] = [];
+    let send: ((msg: ClientMsg) => void) | null = null;
+
+    const ready = (sender: (msg: ClientMsg) => void) => {
+        send = sender;
+        pending.splice(0).forEach(sender);
+    };
+
+    const transport: Transport = Subject.create({
+        next: (msg: ClientMsg) => send ? send(msg) : pending.push(msg),
+        error: () => undefined,
+        complete: () => undefined,
+    }, incoming);
+
+    const forceSse = new URLSearchParams(window.location.search).get('bs3-transport') === 'sse';
+    if (forceSse || !('WebSocket' in window)) {
+        sse(incoming, ready, onClose);
+        return transport;
+    }
+
+    let opened = false;
+    const ws = webSocket<ClientMsg>({
+        url: wsUri,
+        openObserver: {
+            next: () => {
+                opened = true;
+                ready(msg => ws.next(msg));
+            }
+        },
+        closeObserver: {next: () => opened && onClose()},
+    });
+    ws.subscribe({
+        next: msg => incoming.next(msg),
+        error: () => {
+            if (!opened) {
+                console.debug('[bs3] websocket unavailable, falling back to server-sent events');
+                sse(incoming, ready, onClose);
+            }
+        },
+    });
+
+    return transport;
+}
+
+function sse(incoming: Subject<ClientMsg>, ready: (sender: (msg: ClientMsg) => void) => void, onClose: () => void) {
+    const source = new EventSource(SSE_PATH);
+    // POSTs are chained, so that messages arrive in the order they were sent
+    let queue: Promise<unknown> = Promise.resolve();
+
+    source.addEventListener('session', (evt) => {
+        const id = (evt as MessageEvent).data;
+        ready(msg => {
+            queue = queue.then(() => fetch(MSG_PATH + '?id=' + encodeURIComponent(id), {
+                method: 'POST',
+                headers: {'content-type': 'application/json'},
+                body: JSON.stringify(msg),
+            })).catch(() => undefined);
+        });
+    });
+    source.onmessage = (evt) => {
+        try {
+            incoming.next(JSON.parse(evt.data));
+        } catch (e) {
+            console.error('[bs3] invalid message', evt.data);
+        }
+    };
+    // EventSource reconnects by itself, but that would create a new session
+    // without a Hello - treat it like a dropped WebSocket instead
+    source.onerror = () => {
+        source.close();
+        onClose();
+    };
+}
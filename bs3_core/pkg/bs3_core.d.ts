@@ -5,10 +5,49 @@ export type ServedFile = { path: string; web_path: string; referer: string | nul
 export type ClientMsg = 
  | { kind: "Connect" } 
  | { kind: "Disconnect" } 
+ | { kind: "Hello"; payload: { url: string; user_agent: string; version: number; features: string[]; relay_token: string | null } } 
+ | { kind: "Welcome"; payload: WelcomeMsg } 
+ | { kind: "HardReload"; payload: { reason: string } } 
+ | { kind: "Viewport"; payload: ViewportMsg } 
  | { kind: "Scroll"; payload: ScrollMsg } 
- | { kind: "FsNotify"; payload: FsNotify };
+ | { kind: "Click"; payload: ClickMsg } 
+ | { kind: "Input"; payload: InputMsg } 
+ | { kind: "Toggle"; payload: ToggleMsg } 
+ | { kind: "Submit"; payload: SubmitMsg } 
+ | { kind: "Navigate"; payload: NavigateMsg } 
+ | { kind: "Log"; payload: { level: LogLevel; args: string[]; stack: string | null; url: string | null } } 
+ | { kind: "InjectCss"; payload: { web_path: string } } 
+ | { kind: "ReplaceImage"; payload: { web_path: string } } 
+ | { kind: "Reload"; payload: { reason: string } } 
+ | { kind: "Notify"; payload: { message: string; timeout: number | null } } 
+ | { kind: "ErrorOverlay"; payload: ErrorOverlayMsg } 
+ | { kind: "ClearOverlay" } 
+ | { kind: "Eval"; payload: { id: number; code: string } } 
+ | { kind: "EvalResult"; payload: { id: number; value: string | null; error: string | null } } 
+ | { kind: "Relay"; payload: RelayMsg } 
+ | { kind: "HmrUpdate"; payload: { modules: string[]; timestamp: number } } 
+ | { kind: "HmrAccept"; payload: { module: string; deps: string[] } };
 
 export type FsNotify = { item: ServedFile };
 
+export type LogLevel = "debug" | "log" | "info" | "warn" | "error";
+
+export type WelcomeMsg = { version: number; features: string[]; reload_rules: string[] };
+
+export type RelayMsg = { id: number; room: string | null; msg: ClientMsg };
+
+export type ViewportMsg = { width: number; height: number };
+
 export type ScrollMsg = { x: number; y: number };
 
+export type ClickMsg = { selector: string };
+
+export type InputMsg = { selector: string; value: string };
+
+export type ToggleMsg = { selector: string; checked: boolean };
+
+export type SubmitMsg = { selector: string };
+
+export type NavigateMsg = { path: string; query: string | null; hash: string | null };
+
+export type ErrorOverlayMsg = { title: string; body: string; file: string | null; line: number | null };
@@ -0,0 +1,347 @@
+use crate::ws::client::{ClientMsg, ErrorOverlayMsg, NavigateMsg};
+use crate::ws::eval::EvalError;
+use crate::ws::recording::{recording_path, RecordingError, RECORDINGS_DIR};
+use crate::ws::server::{
+    Eval, ListClients, Replay, SendAll, StartRecording, StopRecording, StopReplay, WsServer,
+};
+use actix::Addr;
+use actix_web::error::{ErrorBadRequest, ErrorForbidden, ErrorInternalServerError, ErrorNotFound};
+use actix_web::{web, Error, HttpRequest, HttpResponse, Scope};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+///
+/// Control API for scripts & tooling, eg:
+///
+/// ```shell
+/// curl -X POST localhost:8090/__bs3/api/navigate -d '{"url": "/about.html"}' -H 'content-type: application/json'
+/// ```
+///
+/// Only requests from localhost are accepted
+///
+pub fn scope() -> Scope {
+    web::scope("/__bs3/api")
+        .route("/clients", web::get().to(clients))
+        .route("/navigate", web::post().to(navigate))
+        .route("/notify", web::post().to(notify))
+        .route("/overlay", web::post().to(overlay))
+        .route("/overlay", web::delete().to(clear_overlay))
+        .route("/eval", web::post().to(eval))
+        .route("/record/start", web::post().to(record_start))
+        .route("/record/stop", web::post().to(record_stop))
+        .route("/replay", web::post().to(replay))
+        .route("/replay", web::delete().to(stop_replay))
+}
+
+async fn clients(req: HttpRequest, srv: web::Data<Addr<WsServer>>) -> Result<HttpResponse, Error> {
+    local_only(&req)?;
+    let list = srv
+        .send(ListClients)
+        .await
+        .map_err(ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().json(list))
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct NavigateReq {
+    /// either a full URL, or just a path
+    pub url: String,
+}
+
+async fn navigate(
+    req: HttpRequest,
+    body: web::Json<NavigateReq>,
+    srv: web::Data<Addr<WsServer>>,
+) -> Result<HttpResponse, Error> {
+    local_only(&req)?;
+    let msg = navigate_msg(&body.url).map_err(ErrorBadRequest)?;
+    log::debug!("sending all clients to {:?}", msg);
+    srv.do_send(SendAll {
+        msg: ClientMsg::Navigate(msg.clone()),
+    });
+    Ok(HttpResponse::Ok().json(msg))
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct NotifyReq {
+    pub message: String,
+    /// milliseconds, the browser's default is used when omitted
+    pub timeout: Option<u32>,
+}
+
+async fn notify(
+    req: HttpRequest,
+    body: web::Json<NotifyReq>,
+    srv: web::Data<Addr<WsServer>>,
+) -> Result<HttpResponse, Error> {
+    local_only(&req)?;
+    let NotifyReq { message, timeout } = body.into_inner();
+    srv.do_send(SendAll {
+        msg: ClientMsg::Notify { message, timeout },
+    });
+    Ok(HttpResponse::Ok().finish())
+}
+
+async fn overlay(
+    req: HttpRequest,
+    body: web::Json<ErrorOverlayMsg>,
+    srv: web::Data<Addr<WsServer>>,
+) -> Result<HttpResponse, Error> {
+    local_only(&req)?;
+    srv.do_send(SendAll {
+        msg: ClientMsg::ErrorOverlay(body.into_inner()),
+    });
+    Ok(HttpResponse::Ok().finish())
+}
+
+async fn clear_overlay(
+    req: HttpRequest,
+    srv: web::Data<Addr<WsServer>>,
+) -> Result<HttpResponse, Error> {
+    local_only(&req)?;
+    srv.do_send(SendAll {
+        msg: ClientMsg::ClearOverlay,
+    });
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// How long to wait for browsers to reply to an eval, when not given
+const DEFAULT_EVAL_TIMEOUT_MS: u64 = 5000;
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct EvalReq {
+    pub code: String,
+    /// Client ids to run the code in, every client when empty
+    #[serde(default)]
+    pub clients: Vec<usize>,
+    /// milliseconds
+    pub timeout: Option<u64>,
+}
+
+async fn eval(
+    req: HttpRequest,
+    body: web::Json<EvalReq>,
+    srv: web::Data<Addr<WsServer>>,
+) -> Result<HttpResponse, Error> {
+    local_only(&req)?;
+    let EvalReq {
+        code,
+        clients,
+        timeout,
+    } = body.into_inner();
+    let outcomes = srv
+        .send(Eval {
+            code,
+            clients,
+            timeout: Duration::from_millis(timeout.unwrap_or(DEFAULT_EVAL_TIMEOUT_MS)),
+        })
+        .await
+        .map_err(ErrorInternalServerError)?
+        .map_err(|e| match e {
+            EvalError::Disabled => ErrorForbidden(e),
+            EvalError::NoClients => ErrorNotFound(e),
+            EvalError::Cancelled => ErrorInternalServerError(e),
+        })?;
+    Ok(HttpResponse::Ok().json(outcomes))
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct RecordingReq {
+    /// The JSON file to save to, or replay from. Only a file name, it's always
+    /// inside `.bs3/recordings`
+    pub name: String,
+}
+
+impl RecordingReq {
+    fn path(&self) -> Result<PathBuf, Error> {
+        recording_path(Path::new(RECORDINGS_DIR), &self.name).map_err(recording_error)
+    }
+}
+
+async fn record_start(
+    req: HttpRequest,
+    srv: web::Data<Addr<WsServer>>,
+) -> Result<HttpResponse, Error> {
+    local_only(&req)?;
+    srv.do_send(StartRecording);
+    Ok(HttpResponse::Ok().finish())
+}
+
+async fn record_stop(
+    req: HttpRequest,
+    body: web::Json<RecordingReq>,
+    srv: web::Data<Addr<WsServer>>,
+) -> Result<HttpResponse, Error> {
+    local_only(&req)?;
+    let events = srv
+        .send(StopRecording { path: body.path()? })
+        .await
+        .map_err(ErrorInternalServerError)?
+        .map_err(recording_error)?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "events": events })))
+}
+
+async fn replay(
+    req: HttpRequest,
+    body: web::Json<RecordingReq>,
+    srv: web::Data<Addr<WsServer>>,
+) -> Result<HttpResponse, Error> {
+    local_only(&req)?;
+    let events = srv
+        .send(Replay { path: body.path()? })
+        .await
+        .map_err(ErrorInternalServerError)?
+        .map_err(recording_error)?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "events": events })))
+}
+
+async fn stop_replay(
+    req: HttpRequest,
+    srv: web::Data<Addr<WsServer>>,
+) -> Result<HttpResponse, Error> {
+    local_only(&req)?;
+    srv.do_send(StopReplay);
+    Ok(HttpResponse::Ok().finish())
+}
+
+fn recording_error(e: RecordingError) -> Error {
+    match e {
+        RecordingError::Io(_) => ErrorInternalServerError(e),
+        RecordingError::NotRecording
+        | RecordingError::Json(_)
+        | RecordingError::NotGhostMode(_)
+        | RecordingError::InvalidName(_) => ErrorBadRequest(e),
+    }
+}
+
+fn local_only(req: &HttpRequest) -> Result<(), Error> {
+    match req.peer_addr() {
+        Some(addr) if addr.ip().is_loopback() => Ok(()),
+        _ => Err(ErrorForbidden("the bs3 api is only available on localhost")),
+    }
+}
+
+///
+/// Only the path, query & hash are kept - each browser stays on the
+/// host it's already using (eg: LAN IP vs localhost)
+///
+fn navigate_msg(input: &str) -> Result<NavigateMsg, url::ParseError> {
+    let base = url::Url::parse("http://localhost/").expect("valid base url");
+    let url = base.join(input)?;
+    Ok(NavigateMsg {
+        path: url.path().to_string(),
+        query: url.query().map(String::from),
+        hash: url.fragment().map(String::from),
+    })
+}
+
+#[test]
+fn test_navigate_msg() -> anyhow::Result<()> {
+    let msg = navigate_msg("http://localhost:8090/about.html?a=b#team")?;
+    assert_eq!(
+        msg,
+        NavigateMsg {
+            path: String::from("/about.html"),
+            query: Some(String::from("a=b")),
+            hash: Some(String::from("team")),
+        }
+    );
+    let msg = navigate_msg("/contact")?;
+    assert_eq!(msg.path, "/contact");
+    assert_eq!(msg.query, None);
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn test_notify_and_overlay() -> anyhow::Result<()> {
+    use crate::test_utils::Collector;
+    use actix::Actor;
+    use actix_web::test::{self, TestRequest};
+    use actix_web::App;
+
+    let srv = WsServer::default().start();
+    let (_, browser) = Collector::connect(&srv).await;
+    let mut app = test::init_service(App::new().data(srv.clone()).service(scope())).await;
+    let local: std::net::SocketAddr = "127.0.0.1:40000".parse()?;
+
+    let req = TestRequest::post()
+        .uri("/__bs3/api/notify")
+        .peer_addr(local)
+        .set_json(&serde_json::json!({ "message": "built", "timeout": 1000 }))
+        .to_request();
+    assert_eq!(test::call_service(&mut app, req).await.status(), 200);
+
+    let overlay = ErrorOverlayMsg {
+        title: String::from("Build failed"),
+        body: String::from("unexpected token"),
+        file: Some(String::from("src/app.js")),
+        line: Some(3),
+    };
+    let req = TestRequest::post()
+        .uri("/__bs3/api/overlay")
+        .peer_addr(local)
+        .set_json(&overlay)
+        .to_request();
+    assert_eq!(test::call_service(&mut app, req).await.status(), 200);
+
+    let req = TestRequest::delete()
+        .uri("/__bs3/api/overlay")
+        .peer_addr(local)
+        .to_request();
+    assert_eq!(test::call_service(&mut app, req).await.status(), 200);
+
+    // only from localhost
+    let req = TestRequest::post()
+        .uri("/__bs3/api/notify")
+        .peer_addr("192.168.0.5:40000".parse()?)
+        .set_json(&serde_json::json!({ "message": "remote" }))
+        .to_request();
+    assert_eq!(test::call_service(&mut app, req).await.status(), 403);
+
+    actix_rt::time::delay_for(Duration::from_millis(50)).await;
+    let received: Vec<ClientMsg> = browser
+        .received()
+        .into_iter()
+        .filter(|msg| !matches!(msg, ClientMsg::Connect))
+        .collect();
+    assert_eq!(
+        received,
+        vec![
+            ClientMsg::Notify {
+                message: String::from("built"),
+                timeout: Some(1000),
+            },
+            ClientMsg::ErrorOverlay(overlay),
+            ClientMsg::ClearOverlay,
+        ]
+    );
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn test_recordings_stay_in_their_directory() -> anyhow::Result<()> {
+    use actix::Actor;
+    use actix_web::test::{self, TestRequest};
+    use actix_web::App;
+
+    let srv = WsServer::default().start();
+    let mut app = test::init_service(App::new().data(srv).service(scope())).await;
+    let local: std::net::SocketAddr = "127.0.0.1:40000".parse()?;
+
+    let req = TestRequest::post()
+        .uri("/__bs3/api/record/start")
+        .peer_addr(local)
+        .to_request();
+    assert_eq!(test::call_service(&mut app, req).await.status(), 200);
+    for name in &["../Cargo.toml", "/tmp/bs3-recording.json"] {
+        for uri in &["/__bs3/api/record/stop", "/__bs3/api/replay"] {
+            let req = TestRequest::post()
+                .uri(uri)
+                .peer_addr(local)
+                .set_json(&serde_json::json!({ "name": name }))
+                .to_request();
+            assert_eq!(test::call_service(&mut app, req).await.status(), 400);
+        }
+    }
+    Ok(())
+}
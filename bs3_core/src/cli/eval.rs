@@ -0,0 +1,76 @@
+use crate::api::EvalReq;
+use crate::ws::eval::EvalOutcome;
+use actix_web::client::Client;
+use structopt::StructOpt;
+
+/// Responses can include a value per connected device
+const MAX_RESPONSE_BYTES: usize = 1024 * 1024;
+
+///
+/// `bs3 eval <code>` - run JavaScript in browsers connected to an
+/// already-running bs3, which must have been started with `--allow-eval`
+///
+/// eg: `bs3 eval "navigator.userAgent" --port 8090`
+///
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+pub struct EvalCmd {
+    /// The JavaScript to run, eg: `document.title`
+    pub code: String,
+    /// The port the running bs3 is listening on
+    #[structopt(long = "port", default_value = "8090")]
+    pub port: u16,
+    /// Only run in these client ids, see `/__bs3/api/clients`. Every client when omitted
+    #[structopt(long = "client")]
+    pub clients: Vec<usize>,
+    /// How long to wait for browsers to reply, in milliseconds
+    #[structopt(long = "timeout", default_value = "5000")]
+    pub timeout: u64,
+}
+
+impl EvalCmd {
+    pub fn try_from_args(args: impl Iterator<Item = impl Into<String>>) -> anyhow::Result<Self> {
+        let mut prefix = vec!["bs3 eval".to_string()];
+        prefix.extend(args.map(|arg| arg.into()));
+        Ok(EvalCmd::from_iter_safe(prefix)?)
+    }
+
+    pub async fn run(self) -> anyhow::Result<()> {
+        let url = format!("http://127.0.0.1:{}/__bs3/api/eval", self.port);
+        let body = EvalReq {
+            code: self.code,
+            clients: self.clients,
+            timeout: Some(self.timeout),
+        };
+        let mut res = Client::new()
+            .post(&url)
+            .send_json(&body)
+            .await
+            .map_err(|e| anyhow::anyhow!("could not reach bs3 at {}: {}", url, e))?;
+        if !res.status().is_success() {
+            let text = res.body().await?;
+            anyhow::bail!("{}", String::from_utf8_lossy(&text));
+        }
+        let outcomes: Vec<EvalOutcome> = res.json().limit(MAX_RESPONSE_BYTES).await?;
+        for outcome in outcomes {
+            println!("{}", format_outcome(&outcome));
+        }
+        Ok(())
+    }
+}
+
+fn format_outcome(outcome: &EvalOutcome) -> String {
+    match (&outcome.value, &outcome.error) {
+        (_, Some(error)) => format!("[{}] error: {}", outcome.device, error),
+        (Some(value), None) => format!("[{}] {}", outcome.device, value),
+        (None, None) => format!("[{}] undefined", outcome.device),
+    }
+}
+
+#[test]
+fn test_eval_cmd_from_args() -> anyhow::Result<()> {
+    let cmd = EvalCmd::try_from_args(vec!["document.title", "--client", "12"].into_iter())?;
+    assert_eq!(cmd.code, "document.title");
+    assert_eq!(cmd.port, 8090);
+    assert_eq!(cmd.clients, vec![12]);
+    Ok(())
+}
@@ -0,0 +1,74 @@
+use crate::api::NavigateReq;
+use crate::ws::client::NavigateMsg;
+use actix_web::client::Client;
+use structopt::StructOpt;
+
+///
+/// `bs3 navigate <url>` - send every browser connected to an already-running
+/// bs3 to another page. Each one stays on the host it's already using
+///
+/// eg: `bs3 navigate /about.html --port 8090`
+///
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+pub struct NavigateCmd {
+    /// Either a full URL, or just a path, eg: `/about.html?tab=team#contact`
+    pub url: String,
+    /// The port the running bs3 is listening on
+    #[structopt(long = "port", default_value = "8090")]
+    pub port: u16,
+}
+
+impl NavigateCmd {
+    pub fn try_from_args(args: impl Iterator<Item = impl Into<String>>) -> anyhow::Result<Self> {
+        let mut prefix = vec!["bs3 navigate".to_string()];
+        prefix.extend(args.map(|arg| arg.into()));
+        Ok(NavigateCmd::from_iter_safe(prefix)?)
+    }
+
+    pub async fn run(self) -> anyhow::Result<()> {
+        let url = format!("http://127.0.0.1:{}/__bs3/api/navigate", self.port);
+        let body = NavigateReq { url: self.url };
+        let mut res = Client::new()
+            .post(&url)
+            .send_json(&body)
+            .await
+            .map_err(|e| anyhow::anyhow!("could not reach bs3 at {}: {}", url, e))?;
+        if !res.status().is_success() {
+            let text = res.body().await?;
+            anyhow::bail!("{}", String::from_utf8_lossy(&text));
+        }
+        let msg: NavigateMsg = res.json().await?;
+        println!("sent every browser to {}", format_target(&msg));
+        Ok(())
+    }
+}
+
+fn format_target(msg: &NavigateMsg) -> String {
+    let mut target = msg.path.clone();
+    if let Some(query) = &msg.query {
+        target.push('?');
+        target.push_str(query);
+    }
+    if let Some(hash) = &msg.hash {
+        target.push('#');
+        target.push_str(hash);
+    }
+    target
+}
+
+#[test]
+fn test_navigate_cmd_from_args() -> anyhow::Result<()> {
+    let cmd = NavigateCmd::try_from_args(vec!["/about.html", "--port", "3000"].into_iter())?;
+    assert_eq!(cmd.url, "/about.html");
+    assert_eq!(cmd.port, 3000);
+    assert!(NavigateCmd::try_from_args(Vec::<String>::new().into_iter()).is_err());
+    assert_eq!(
+        format_target(&NavigateMsg {
+            path: String::from("/about.html"),
+            query: Some(String::from("tab=team")),
+            hash: Some(String::from("contact")),
+        }),
+        "/about.html?tab=team#contact"
+    );
+    Ok(())
+}
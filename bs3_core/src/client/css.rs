@@ -1,3 +1,4 @@
+use crate::resp::stream::{Replace, StreamMod};
 use crate::resp::RespMod;
 use actix_web::dev::{RequestHead, ResponseHead};
 
@@ -11,6 +12,9 @@ impl RespMod for Css {
     fn process_str(&self, str: String) -> String {
         str.replace("Chunked", "[Chunked]")
     }
+    fn stream(&self) -> Option<Box<dyn StreamMod>> {
+        Some(Box::new(Replace::new("Chunked", "[Chunked]")))
+    }
     fn guard(&self, req_head: &RequestHead, _res_head: &ResponseHead) -> bool {
         req_head
             .uri
@@ -0,0 +1,52 @@
+use crate::resp::RespMod;
+use crate::ws::hmr::ModuleGraph;
+use actix_web::dev::{RequestHead, ResponseHead};
+use actix_web::http::header::CONTENT_TYPE;
+
+/// Prepended to modules, `window.__bs3_hmr` is set up by the bs3 client
+const HOT: &str = "import.meta.hot = window.__bs3_hmr && window.__bs3_hmr.hot(import.meta.url);\n";
+
+///
+/// Browsers don't provide `import.meta.hot`, so ES modules that use it are given
+/// one by the bs3 client. Only added with `--hmr`.
+///
+/// Classic scripts are left alone, `import.meta` is a syntax error outside of modules
+///
+#[derive(Debug, Clone)]
+pub struct HotContext;
+
+impl RespMod for HotContext {
+    fn name(&self) -> String {
+        String::from("bs3 import.meta.hot")
+    }
+    fn process_str(&self, str: String) -> String {
+        if !str.contains("import.meta.hot") || !ModuleGraph::is_es_module(&str) {
+            return str;
+        }
+        format!("{}{}", HOT, str)
+    }
+    fn guard(&self, req_head: &RequestHead, res_head: &ResponseHead) -> bool {
+        // the bs3 client itself is a classic script
+        let is_js = res_head
+            .headers
+            .get(CONTENT_TYPE)
+            .and_then(|hv| hv.to_str().ok())
+            .filter(|str| str.contains("javascript"))
+            .is_some();
+        is_js && !req_head.uri.path().starts_with("/__bs3/")
+    }
+}
+
+#[test]
+fn test_hot_context() {
+    let source = String::from("if (import.meta.hot) import.meta.hot.accept();");
+    let output = HotContext.process_str(source.clone());
+    assert!(output.starts_with("import.meta.hot = window.__bs3_hmr"));
+    assert!(output.ends_with(&source));
+
+    let plain = String::from("export const a = 1;");
+    assert_eq!(HotContext.process_str(plain.clone()), plain);
+
+    let classic = String::from("// import.meta.hot is for modules\nwindow.a = 1;");
+    assert_eq!(HotContext.process_str(classic.clone()), classic);
+}
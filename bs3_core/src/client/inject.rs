@@ -0,0 +1,484 @@
+use crate::resp::csp::CspAllow;
+use crate::resp::stream::{partial_match_len, StreamMod};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+///
+/// Elements whose content is text rather than markup, so a `</body>`
+/// inside them is not a tag
+///
+const RAW_TEXT: &[&str] = &["script", "style", "textarea", "title"];
+
+///
+/// Where in an HTML document a snippet is injected
+///
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum InjectPosition {
+    /// Just before `</head>`
+    HeadEnd,
+    /// Just after the opening `<body>` tag
+    BodyStart,
+    /// Just before `</body>`
+    BodyEnd,
+}
+
+impl Default for InjectPosition {
+    fn default() -> Self {
+        InjectPosition::BodyEnd
+    }
+}
+
+impl FromStr for InjectPosition {
+    type Err = InjectError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "head-end" => Ok(InjectPosition::HeadEnd),
+            "body-start" => Ok(InjectPosition::BodyStart),
+            "body-end" => Ok(InjectPosition::BodyEnd),
+            _ => Err(InjectError::InvalidPosition(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for InjectPosition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let as_str = match self {
+            InjectPosition::HeadEnd => "head-end",
+            InjectPosition::BodyStart => "body-start",
+            InjectPosition::BodyEnd => "body-end",
+        };
+        write!(f, "{}", as_str)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum InjectError {
+    #[error("invalid inject position `{0}`, expected one of: head-end, body-start, body-end")]
+    InvalidPosition(String),
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum State {
+    Markup,
+    /// Inside `<!-- -->`
+    Comment,
+    /// Inside one of `RAW_TEXT`, until its closing tag
+    RawText(&'static str),
+    /// The snippet was injected, everything else passes through
+    Done,
+}
+
+enum Token {
+    /// Not enough input to tell what this is yet
+    Incomplete,
+    /// Text that isn't a tag, including doctypes & processing instructions
+    Text(usize),
+    /// The start of a comment, `<!--`
+    Comment,
+    Tag {
+        len: usize,
+        name: String,
+        closing: bool,
+    },
+}
+
+///
+/// Injects a snippet into an HTML document, at the first matching tag that's
+/// not inside a comment, script, style etc.
+///
+/// When the document doesn't have the tag for `position` (all of them are optional),
+/// the fallbacks are:
+///
+/// - `head-end`: before `<body>`, then `</body>`
+/// - `body-start`/`body-end`: before `</body>`
+///
+/// then before `</html>`, and finally at the end of the document.
+///
+/// As a `StreamMod`, anything that may be the start of a tag is held back until
+/// the next chunk
+///
+#[derive(Debug, Clone)]
+pub struct Injector {
+    position: InjectPosition,
+    snippet: String,
+    /// `<meta>` Content-Security-Policies seen before the snippet are changed to allow it
+    csp: Option<CspAllow>,
+    pending: String,
+    state: State,
+}
+
+impl Injector {
+    pub fn new(position: InjectPosition, snippet: impl Into<String>) -> Self {
+        Self {
+            position,
+            snippet: snippet.into(),
+            csp: None,
+            pending: String::new(),
+            state: State::Markup,
+        }
+    }
+
+    pub fn with_csp(mut self, csp: CspAllow) -> Self {
+        self.csp = Some(csp);
+        self
+    }
+
+    /// Inject into an entire document
+    pub fn inject(mut self, html: String) -> String {
+        let mut output = self.process_chunk(html);
+        output.push_str(&self.finish());
+        output
+    }
+
+    /// Consume as much of `pending` as possible, `end` when no more input will follow
+    fn run(&mut self, end: bool) -> String {
+        let mut output = String::with_capacity(self.pending.len());
+        loop {
+            match self.state {
+                State::Done => {
+                    output.push_str(&std::mem::take(&mut self.pending));
+                    break;
+                }
+                State::Comment => {
+                    if let Some(index) = self.pending.find("-->") {
+                        output.extend(self.pending.drain(..index + "-->".len()));
+                        self.state = State::Markup;
+                        continue;
+                    }
+                    let keep = if end {
+                        0
+                    } else {
+                        partial_match_len(&self.pending, "-->")
+                    };
+                    output.extend(self.pending.drain(..self.pending.len() - keep));
+                    break;
+                }
+                State::RawText(name) => {
+                    // ascii lowercase keeps every byte offset the same
+                    let lower = self.pending.to_ascii_lowercase();
+                    let close = format!("</{}", name);
+                    if let Some(index) = lower.find(&close) {
+                        // the closing tag itself is read as markup
+                        output.extend(self.pending.drain(..index));
+                        self.state = State::Markup;
+                        continue;
+                    }
+                    let keep = if end {
+                        0
+                    } else {
+                        partial_match_len(&lower, &close)
+                    };
+                    output.extend(self.pending.drain(..self.pending.len() - keep));
+                    break;
+                }
+                State::Markup => {
+                    let index = match self.pending.find('<') {
+                        Some(index) => index,
+                        None => {
+                            output.push_str(&std::mem::take(&mut self.pending));
+                            break;
+                        }
+                    };
+                    output.extend(self.pending.drain(..index));
+                    match next_token(&self.pending, end) {
+                        Token::Incomplete => break,
+                        Token::Text(len) => output.extend(self.pending.drain(..len)),
+                        Token::Comment => {
+                            output.extend(self.pending.drain(.."<!--".len()));
+                            self.state = State::Comment;
+                        }
+                        Token::Tag { len, name, closing } => {
+                            let tag: String = self.pending.drain(..len).collect();
+                            self.tag(&tag, &name, closing, &mut output);
+                        }
+                    }
+                }
+            }
+        }
+        output
+    }
+
+    fn tag(&mut self, tag: &str, name: &str, closing: bool, output: &mut String) {
+        let tag = match &self.csp {
+            Some(csp) if name == "meta" && !closing => csp.apply_meta(tag),
+            _ => tag.to_string(),
+        };
+        let tag = tag.as_str();
+        let before = matches!(
+            (self.position, name, closing),
+            (_, "html", true)
+                | (_, "body", true)
+                | (InjectPosition::HeadEnd, "head", true)
+                | (InjectPosition::HeadEnd, "body", false)
+        );
+        if before {
+            output.push_str(&self.snippet);
+            output.push_str(tag);
+            self.state = State::Done;
+            return;
+        }
+        output.push_str(tag);
+        if self.position == InjectPosition::BodyStart && name == "body" && !closing {
+            output.push_str(&self.snippet);
+            self.state = State::Done;
+            return;
+        }
+        if !closing && !tag.ends_with("/>") {
+            if let Some(raw) = RAW_TEXT.iter().find(|raw| **raw == name) {
+                self.state = State::RawText(raw);
+            }
+        }
+    }
+}
+
+impl StreamMod for Injector {
+    fn process_chunk(&mut self, chunk: String) -> String {
+        self.pending.push_str(&chunk);
+        self.run(false)
+    }
+    fn finish(&mut self) -> String {
+        let mut output = self.run(true);
+        if self.state != State::Done {
+            output.push_str(&self.snippet);
+            self.state = State::Done;
+        }
+        output
+    }
+    fn held_back(&self) -> &str {
+        &self.pending
+    }
+}
+
+///
+/// Read the token at the start of `text`, which begins with `<`
+///
+fn next_token(text: &str, end: bool) -> Token {
+    let incomplete = || {
+        if end {
+            Token::Text(text.len())
+        } else {
+            Token::Incomplete
+        }
+    };
+    if text.starts_with("<!--") {
+        return Token::Comment;
+    }
+    let (closing, name_start) = match text[1..].chars().next() {
+        None => return incomplete(),
+        // eg: a chunk that ends with `<!-`
+        Some('!') if "<!--".starts_with(text) => return incomplete(),
+        // doctypes, CDATA & processing instructions
+        Some('!') | Some('?') => {
+            return match text.find('>') {
+                Some(index) => Token::Text(index + 1),
+                None => incomplete(),
+            }
+        }
+        Some('/') => (true, 2),
+        Some(_) => (false, 1),
+    };
+    // like browsers, only a letter can start a tag name
+    let starts_name = text[name_start..]
+        .chars()
+        .next()
+        .map_or(false, |c| c.is_ascii_alphabetic());
+    let name: String = if starts_name {
+        text[name_start..]
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric())
+            .collect()
+    } else {
+        String::new()
+    };
+    if name.is_empty() {
+        // eg: `a < b`, `I <3 bs3`, or a chunk that ends with `</`
+        return if name_start == text.len() {
+            incomplete()
+        } else {
+            Token::Text(1)
+        };
+    }
+    // quotes only matter in attribute values, ie: straight after `=`
+    let mut quote = None;
+    let mut value_next = false;
+    for (index, c) in text.char_indices().skip(name_start + name.len()) {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '=') => value_next = true,
+            (None, c) if c.is_ascii_whitespace() => {}
+            (None, '"') | (None, '\'') if value_next => {
+                quote = Some(c);
+                value_next = false;
+            }
+            (None, '>') => {
+                return Token::Tag {
+                    len: index + 1,
+                    name: name.to_ascii_lowercase(),
+                    closing,
+                }
+            }
+            _ => value_next = false,
+        }
+    }
+    incomplete()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SNIPPET: &str = "<script src=\"bs3.js\"></script>";
+
+    fn inject(position: InjectPosition, html: &str) -> String {
+        Injector::new(position, SNIPPET).inject(html.to_string())
+    }
+
+    fn inject_chunks(position: InjectPosition, html: &str, size: usize) -> String {
+        let mut injector = Injector::new(position, SNIPPET);
+        let mut output = String::new();
+        let chars: Vec<char> = html.chars().collect();
+        for chunk in chars.chunks(size) {
+            output.push_str(&injector.process_chunk(chunk.iter().collect()));
+        }
+        output.push_str(&injector.finish());
+        output
+    }
+
+    const PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<title>a </body> title</title>
+<!-- <head></head><body></body> -->
+<script>document.write("</head><body></body>")</script>
+<style>body::after { content: "</body>" }</style>
+</head>
+<BODY class="a>b">
+<p>1 < 2</p>
+<p>I <3 bs3's <em title='it"s'>docs</em></p>
+<textarea></body></textarea>
+</Body>
+</html>"#;
+
+    #[test]
+    fn test_positions() {
+        let output = inject(InjectPosition::BodyEnd, PAGE);
+        assert_eq!(
+            output,
+            PAGE.replace(
+                "</textarea>\n</Body>",
+                "</textarea>\n<script src=\"bs3.js\"></script></Body>"
+            )
+        );
+        let output = inject(InjectPosition::HeadEnd, PAGE);
+        assert_eq!(
+            output,
+            PAGE.replace(
+                "</style>\n</head>",
+                "</style>\n<script src=\"bs3.js\"></script></head>"
+            )
+        );
+        let output = inject(InjectPosition::BodyStart, PAGE);
+        assert_eq!(
+            output,
+            PAGE.replace("\"a>b\">\n", "\"a>b\"><script src=\"bs3.js\"></script>\n")
+        );
+    }
+
+    #[test]
+    fn test_streamed() {
+        for position in &[
+            InjectPosition::HeadEnd,
+            InjectPosition::BodyStart,
+            InjectPosition::BodyEnd,
+        ] {
+            for size in 1..8 {
+                assert_eq!(
+                    inject_chunks(*position, PAGE, size),
+                    inject(*position, PAGE),
+                    "{} in chunks of {}",
+                    position,
+                    size
+                );
+            }
+        }
+        // nothing is held back after an apostrophe in text
+        for size in 1..8 {
+            assert_eq!(
+                inject_chunks(
+                    InjectPosition::BodyEnd,
+                    "<p>I <3 bs3's</p></body></html>",
+                    size
+                ),
+                format!("<p>I <3 bs3's</p>{}</body></html>", SNIPPET),
+                "in chunks of {}",
+                size
+            );
+        }
+    }
+
+    #[test]
+    fn test_fallbacks() {
+        // no </body>
+        assert_eq!(
+            inject(InjectPosition::BodyEnd, "<html><body><p>hi</html>"),
+            format!("<html><body><p>hi{}</html>", SNIPPET)
+        );
+        assert_eq!(
+            inject(InjectPosition::BodyEnd, "<p>hi"),
+            format!("<p>hi{}", SNIPPET)
+        );
+        // no </head>
+        assert_eq!(
+            inject(InjectPosition::HeadEnd, "<head><title>t</title><body>"),
+            format!("<head><title>t</title>{}<body>", SNIPPET)
+        );
+        // no <body>
+        assert_eq!(
+            inject(InjectPosition::BodyStart, "<head></head><p>hi</p></body>"),
+            format!("<head></head><p>hi</p>{}</body>", SNIPPET)
+        );
+        // not tags, and an apostrophe outside of an attribute value
+        assert_eq!(
+            inject(InjectPosition::BodyEnd, "<p>I <3 bs3's</p></body>"),
+            format!("<p>I <3 bs3's</p>{}</body>", SNIPPET)
+        );
+        // only ever once
+        assert_eq!(
+            inject(InjectPosition::BodyEnd, "</body></body>"),
+            format!("{}</body></body>", SNIPPET)
+        );
+    }
+
+    #[test]
+    fn test_position_from_str() {
+        assert_eq!(
+            "head-end".parse::<InjectPosition>().ok(),
+            Some(InjectPosition::HeadEnd)
+        );
+        assert!("head".parse::<InjectPosition>().is_err());
+    }
+
+    #[test]
+    fn test_meta_csp() {
+        let csp = CspAllow {
+            nonce: String::from("abc"),
+            connect: vec![String::from("'self'")],
+            eval: false,
+        };
+        let html = r#"<head><meta http-equiv="content-security-policy" content="script-src 'self'"></head>"#;
+        let output = Injector::new(InjectPosition::HeadEnd, SNIPPET)
+            .with_csp(csp)
+            .inject(html.to_string());
+        assert_eq!(
+            output,
+            format!(
+                r#"<head><meta http-equiv="content-security-policy" content="script-src 'self' 'nonce-abc'">{}</head>"#,
+                SNIPPET
+            )
+        );
+    }
+}
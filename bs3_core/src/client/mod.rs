@@ -1,2 +1,4 @@
 pub mod css;
+pub mod hmr;
+pub mod inject;
 pub mod script;
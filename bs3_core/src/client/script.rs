@@ -1,20 +1,203 @@
+use crate::client::inject::{InjectPosition, Injector};
+use crate::resp::csp::CspAllow;
+use crate::resp::stream::StreamMod;
 use crate::resp::RespMod;
 use actix_web::dev::{RequestHead, ResponseHead};
-use actix_web::http::header::{ACCEPT, CONTENT_TYPE};
+use actix_web::http::header::{ACCEPT, CONTENT_TYPE, HOST};
 use actix_web::http::HeaderMap;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+use structopt::StructOpt;
 
+const CLIENT_SRC: &str = "/__bs3/client/index.js";
+
+///
+/// How the bs3 client `<script>` is added to pages
+///
+#[derive(StructOpt, Default, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ScriptOptions {
+    /// Where the script is injected: head-end, body-start or body-end
+    #[structopt(long = "script-position", default_value = "body-end")]
+    #[serde(default)]
+    pub position: InjectPosition,
+    #[structopt(long = "script-async")]
+    #[serde(rename = "async", default)]
+    pub is_async: bool,
+    #[structopt(long = "script-defer")]
+    #[serde(default)]
+    pub defer: bool,
+    /// Inject it with `type="module"`
+    #[structopt(long = "script-module")]
+    #[serde(default)]
+    pub module: bool,
+    /// Extra attributes, eg: `--script-attr crossorigin=anonymous`
+    #[structopt(long = "script-attr")]
+    #[serde(default)]
+    pub attrs: Vec<ScriptAttr>,
+}
+
+impl ScriptOptions {
+    /// The markup that's injected, `nonce` allows it through a Content-Security-Policy
+    pub fn snippet(&self, nonce: Option<&str>) -> String {
+        let mut attrs = format!(r#"src="{}""#, CLIENT_SRC);
+        if let Some(nonce) = nonce {
+            attrs.push_str(&format!(r#" nonce="{}""#, nonce));
+        }
+        if self.is_async {
+            attrs.push_str(" async");
+        }
+        if self.defer {
+            attrs.push_str(" defer");
+        }
+        if self.module {
+            attrs.push_str(r#" type="module""#);
+        }
+        for attr in &self.attrs {
+            attrs.push(' ');
+            attrs.push_str(&attr.to_string());
+        }
+        format!(
+            "\n<!-- injected by Browsersync -->\n<script {}></script>\n<!-- end:injected by Browsersync -->\n",
+            attrs
+        )
+    }
+}
+
+///
+/// An extra attribute for the injected script, in the form `name=value`, or
+/// just `name` for boolean attributes
+///
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ScriptAttr {
+    pub name: String,
+    pub value: Option<String>,
+}
+
+impl FromStr for ScriptAttr {
+    type Err = ScriptAttrError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut split = s.splitn(2, '=');
+        let name = split.next().unwrap_or_default().trim();
+        let invalid = |c: char| c.is_whitespace() || "\"'<>/=".contains(c);
+        if name.is_empty() || name.contains(invalid) {
+            return Err(ScriptAttrError::InvalidName(name.to_string()));
+        }
+        Ok(ScriptAttr {
+            name: name.to_string(),
+            value: split.next().map(String::from),
+        })
+    }
+}
+
+/// The attribute as it appears in the tag, with the value escaped
+impl fmt::Display for ScriptAttr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.value {
+            Some(value) => {
+                let escaped = value
+                    .replace('&', "&amp;")
+                    .replace('"', "&quot;")
+                    .replace('<', "&lt;");
+                write!(f, r#"{}="{}""#, self.name, escaped)
+            }
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+impl Serialize for ScriptAttr {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match &self.value {
+            Some(value) => serializer.serialize_str(&format!("{}={}", self.name, value)),
+            None => serializer.serialize_str(&self.name),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ScriptAttr {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        FromStr::from_str(&s).map_err(de::Error::custom)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ScriptAttrError {
+    #[error("invalid script attribute name `{0}`, expected eg: `crossorigin=anonymous`")]
+    InvalidName(String),
+}
+
+///
+/// Injects the bs3 client into HTML pages
+///
 #[derive(Debug, Clone)]
-pub struct Script;
+pub struct Script {
+    options: ScriptOptions,
+    /// Whether the client needs `'unsafe-eval'`, for `--hmr` or `--allow-eval`
+    eval: bool,
+    /// Set for each response, in `process_head`
+    csp: Option<CspAllow>,
+}
+
+impl Script {
+    pub fn new(options: &ScriptOptions) -> Self {
+        Self {
+            options: options.clone(),
+            eval: false,
+            csp: None,
+        }
+    }
+    pub fn with_eval(mut self, eval: bool) -> Self {
+        self.eval = eval;
+        self
+    }
+    fn injector(&self) -> Injector {
+        let nonce = self.csp.as_ref().map(|csp| csp.nonce.as_str());
+        let injector = Injector::new(self.options.position, self.options.snippet(nonce));
+        match &self.csp {
+            Some(csp) => injector.with_csp(csp.clone()),
+            None => injector,
+        }
+    }
+}
+
+impl Default for Script {
+    fn default() -> Self {
+        Script::new(&ScriptOptions::default())
+    }
+}
 
 impl RespMod for Script {
     fn process_str(&self, str: String) -> String {
-        let injected = r#"
-        <!-- injected by Browsersync -->
-        <script src="/__bs3/client/index.js"></script>
-        <!-- end:injected by Browsersync -->
-        </body>
-        "#;
-        str.replace("</body>", injected)
+        self.injector().inject(str)
+    }
+    fn stream(&self) -> Option<Box<dyn StreamMod>> {
+        Some(Box::new(self.injector()))
+    }
+    ///
+    /// Allow the script through any Content-Security-Policy, with a nonce that's
+    /// unique to this response. `<meta>` policies are handled with the body
+    ///
+    fn process_head(
+        &self,
+        req_head: &RequestHead,
+        res_head: &mut ResponseHead,
+    ) -> Option<Box<dyn RespMod>> {
+        let host = req_head.headers.get(HOST).and_then(|hv| hv.to_str().ok());
+        let csp = CspAllow::new(host).with_eval(self.eval);
+        csp.apply_headers(res_head);
+        Some(Box::new(Script {
+            csp: Some(csp),
+            ..self.clone()
+        }))
     }
     fn guard(&self, req_head: &RequestHead, res_head: &ResponseHead) -> bool {
         is_accept_html(&req_head.headers) && is_content_type_html(&res_head.headers)
@@ -39,3 +222,72 @@ fn is_content_type_html(headers: &HeaderMap) -> bool {
         .filter(|str| str.contains("text/html"))
         .is_some()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::header::{HeaderValue, CONTENT_SECURITY_POLICY};
+    use actix_web::http::StatusCode;
+    use actix_web::test::TestRequest;
+
+    fn csp_after(script: &Script) -> String {
+        let req = TestRequest::default()
+            .header(HOST, "localhost:8090")
+            .to_http_request();
+        let mut res_head = ResponseHead::new(StatusCode::OK);
+        res_head.headers.insert(
+            CONTENT_SECURITY_POLICY,
+            HeaderValue::from_static("script-src 'self'"),
+        );
+        script.process_head(req.head(), &mut res_head);
+        res_head
+            .headers
+            .get(CONTENT_SECURITY_POLICY)
+            .and_then(|hv| hv.to_str().ok())
+            .map(String::from)
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn test_csp_eval() {
+        // `--hmr` & `--allow-eval` need eval, nothing else does
+        assert!(!csp_after(&Script::default()).contains("'unsafe-eval'"));
+        let script = Script::default().with_eval(true);
+        let csp = csp_after(&script);
+        assert!(csp.starts_with("script-src 'self' 'nonce-"));
+        assert!(csp.ends_with(
+            " 'unsafe-eval'; connect-src 'self' ws://localhost:8090 wss://localhost:8090"
+        ));
+    }
+
+    #[test]
+    fn test_snippet() -> anyhow::Result<()> {
+        let options = ScriptOptions {
+            is_async: true,
+            module: true,
+            attrs: vec![
+                "crossorigin=anonymous".parse()?,
+                "nomodule".parse()?,
+                r#"data-x=a"b"#.parse()?,
+            ],
+            ..ScriptOptions::default()
+        };
+        assert!(options.snippet(None).contains(
+            r#"<script src="/__bs3/client/index.js" async type="module" crossorigin="anonymous" nomodule data-x="a&quot;b"></script>"#
+        ));
+        assert!("bad name=1".parse::<ScriptAttr>().is_err());
+        assert!("=1".parse::<ScriptAttr>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_options_from_json() -> anyhow::Result<()> {
+        let options: ScriptOptions = serde_json::from_str(
+            r#"{ "position": "head-end", "defer": true, "attrs": ["crossorigin=anonymous"] }"#,
+        )?;
+        assert_eq!(options.position, InjectPosition::HeadEnd);
+        assert!(options.defer && !options.is_async);
+        assert_eq!(options.attrs[0].value.as_deref(), Some("anonymous"));
+        Ok(())
+    }
+}
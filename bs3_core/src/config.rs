@@ -1,5 +1,12 @@
+use crate::client::script::ScriptOptions;
+use crate::header_rules::HeaderRule;
 use crate::proxy::{Proxy, ProxyTarget};
+use crate::reload_rules::ReloadRule;
+use crate::rewrite_rules::RewriteRule;
 use crate::serve_static::{Multi, ServeStatic, ServeStaticConfig};
+use crate::ws::client::LogLevel;
+use crate::ws::ghost::GhostMode;
+use crate::ws::relay::RelayTarget;
 use serde::{Deserialize, Serialize};
 use std::net::TcpListener;
 use std::path::PathBuf;
@@ -21,6 +28,56 @@ pub struct Config {
     #[structopt(long = "port")]
     #[serde(default = "crate::config::default_port")]
     pub port: Option<u16>,
+    #[structopt(long = "reload-rule")]
+    #[serde(rename = "reloadRules", default)]
+    pub reload_rules: Vec<ReloadRule>,
+    #[structopt(flatten)]
+    #[serde(rename = "ghostMode", default)]
+    pub ghost_mode: GhostMode,
+    /// Which browser console levels are printed in the terminal, all of them when empty
+    #[structopt(long = "client-log")]
+    #[serde(rename = "clientLogs", default)]
+    pub client_logs: Vec<LogLevel>,
+    /// Allow JavaScript to be evaluated in connected browsers, via `bs3 eval` or the control API.
+    /// Adds `'unsafe-eval'` to the Content-Security-Policy of pages
+    #[structopt(long = "allow-eval")]
+    #[serde(rename = "allowEval", default)]
+    pub allow_eval: bool,
+    /// Other bs3 instances to share ghost-mode & reload messages with, eg: `ws://192.168.0.5:8090`
+    #[structopt(long = "relay")]
+    #[serde(default)]
+    pub relay: Vec<RelayTarget>,
+    /// A shared secret that relays must present. Other instances are only accepted as
+    /// peers when this is set, and they use the same one
+    #[structopt(long = "relay-token")]
+    #[serde(rename = "relayToken", default)]
+    pub relay_token: Option<String>,
+    /// Serve the LiveReload v7 protocol on `/livereload`, for LiveReload browser extensions.
+    /// `/livereload.js` isn't served, pages that load it should use the bs3 client instead
+    #[structopt(long = "livereload")]
+    #[serde(default)]
+    pub livereload: bool,
+    /// Also serve `/livereload` on this port, the extensions expect 35729. Implies `--livereload`
+    #[structopt(long = "livereload-port")]
+    #[serde(rename = "livereloadPort", default)]
+    pub livereload_port: Option<u16>,
+    /// Hot-swap changed ES modules that call `import.meta.hot.accept()`, instead of reloading.
+    /// Adds `'unsafe-eval'` to the Content-Security-Policy of pages
+    #[structopt(long = "hmr")]
+    #[serde(default)]
+    pub hmr: bool,
+    /// How the bs3 client script is injected into pages
+    #[structopt(flatten)]
+    #[serde(default)]
+    pub script: ScriptOptions,
+    /// Find & replace in response bodies, only available in config files
+    #[structopt(skip)]
+    #[serde(rename = "rewriteRules", default)]
+    pub rewrite_rules: Vec<RewriteRule>,
+    /// Set or remove response headers by path, only available in config files
+    #[structopt(skip)]
+    #[serde(default)]
+    pub headers: Vec<HeaderRule>,
 }
 
 pub fn default_port() -> Option<u16> {
@@ -73,7 +130,10 @@ impl Proxy for Config {
 mod tests {
     use super::*;
     use crate::browser_sync::BrowserSync;
+    use crate::client::inject::InjectPosition;
+    use crate::client::script::ScriptAttr;
     use crate::proxy::ProxyTarget;
+    use crate::reload_rules::RuleAction;
     use std::str::FromStr;
 
     #[test]
@@ -154,6 +214,58 @@ mod tests {
         Ok(())
     }
     #[test]
+    fn test_reload_rules_from_args() -> anyhow::Result<()> {
+        let args = ". --reload-rule ignore:/dist/** --reload-rule reload:**/*.css";
+        let bs = BrowserSync::try_from_args(args.split(" "))?;
+        assert_eq!(
+            vec![
+                ReloadRule::new("/dist/**", RuleAction::Ignore),
+                ReloadRule::new("**/*.css", RuleAction::Reload),
+            ],
+            bs.config.reload_rules
+        );
+        Ok(())
+    }
+    #[test]
+    fn test_ghost_mode_from_args() -> anyhow::Result<()> {
+        let bs = BrowserSync::try_from_args(vec!["."].into_iter())?;
+        assert_eq!(bs.config.ghost_mode, GhostMode::default());
+
+        let args = ". --no-ghost-scroll --no-ghost-submit";
+        let bs = BrowserSync::try_from_args(args.split(" "))?;
+        assert!(!bs.config.ghost_mode.scroll);
+        assert!(bs.config.ghost_mode.clicks);
+        assert!(!bs.config.ghost_mode.forms.submit);
+        Ok(())
+    }
+    #[test]
+    fn test_relay_from_args() -> anyhow::Result<()> {
+        let args = ". --relay ws://localhost:8091";
+        let bs = BrowserSync::try_from_args(args.split(" "))?;
+        assert_eq!(
+            vec![RelayTarget::from_str("ws://localhost:8091/__bs3/ws/")?],
+            bs.config.relay
+        );
+        assert_eq!(bs.config.relay_token, None);
+
+        let args = ". --relay ws://localhost:8091 --relay-token s3cret";
+        let bs = BrowserSync::try_from_args(args.split(" "))?;
+        assert_eq!(bs.config.relay_token.as_deref(), Some("s3cret"));
+        Ok(())
+    }
+    #[test]
+    fn test_script_options_from_args() -> anyhow::Result<()> {
+        let args = ". --script-position head-end --script-defer --script-attr nonce=abc";
+        let bs = BrowserSync::try_from_args(args.split(" "))?;
+        assert_eq!(bs.config.script.position, InjectPosition::HeadEnd);
+        assert!(bs.config.script.defer);
+        assert_eq!(
+            bs.config.script.attrs,
+            vec![ScriptAttr::from_str("nonce=abc")?]
+        );
+        Ok(())
+    }
+    #[test]
     fn test_proxy_from_args_error() {
         let args = "--proxy http:/.example.com";
         let p = url::Url::parse(args);
@@ -162,6 +274,34 @@ mod tests {
         // assert!(bs.is_err());
     }
     #[test]
+    fn test_rewrite_rules_from_json() -> anyhow::Result<()> {
+        let input = r#"
+        {
+            "rewriteRules": [
+                { "match": "UA-1234", "replace": "UA-DEV", "source": "proxy" }
+            ]
+        }
+        "#;
+        let config: Config = serde_json::from_str(input)?;
+        assert_eq!(config.rewrite_rules.len(), 1);
+        assert_eq!(config.rewrite_rules[0].content_type, "text/html");
+        Ok(())
+    }
+    #[test]
+    fn test_headers_from_json() -> anyhow::Result<()> {
+        let input = r#"
+        {
+            "headers": [
+                { "match": "/api/**", "set": { "Access-Control-Allow-Origin": "*" } }
+            ]
+        }
+        "#;
+        let config: Config = serde_json::from_str(input)?;
+        assert_eq!(config.headers[0].glob, "/api/**");
+        assert!(config.headers[0].remove.is_empty());
+        Ok(())
+    }
+    #[test]
     fn test_proxy_from_json() {
         let input = r#"
         {
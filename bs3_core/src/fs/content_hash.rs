@@ -0,0 +1,58 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+
+///
+/// Hash the full contents of a file on disk
+///
+pub fn content_hash(path: &Path) -> io::Result<u64> {
+    let bytes = std::fs::read(path)?;
+    let mut hasher = DefaultHasher::new();
+    bytes.hash(&mut hasher);
+    Ok(hasher.finish())
+}
+
+///
+/// The last-known content hash of every watched file.
+///
+/// Editors, formatters & `git checkout` will often touch files without
+/// altering them - this allows those events to be dropped
+///
+#[derive(Debug, Default)]
+pub struct ContentHashes {
+    items: HashMap<PathBuf, u64>,
+}
+
+impl ContentHashes {
+    ///
+    /// Record the current hash for `path`, returning `false` only when
+    /// it matches the previous one. `None` means the file could not be read
+    /// (eg: it was removed), which always counts as a change
+    ///
+    pub fn update(&mut self, path: &Path, hash: Option<u64>) -> bool {
+        match hash {
+            Some(hash) => self.items.insert(path.to_path_buf(), hash) != Some(hash),
+            None => {
+                self.items.remove(path);
+                true
+            }
+        }
+    }
+    pub fn get(&self, path: &Path) -> Option<u64> {
+        self.items.get(path).copied()
+    }
+}
+
+#[test]
+fn test_content_hashes() {
+    let mut hashes = ContentHashes::default();
+    let pb = PathBuf::from("/style.css");
+    assert!(hashes.update(&pb, Some(1)));
+    assert!(!hashes.update(&pb, Some(1)));
+    assert!(hashes.update(&pb, Some(2)));
+    assert!(hashes.update(&pb, None));
+    assert_eq!(hashes.get(&pb), None);
+    assert!(hashes.update(&pb, Some(2)));
+}
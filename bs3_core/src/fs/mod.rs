@@ -12,10 +12,14 @@ use std::collections::{HashMap, HashSet};
 use std::sync::mpsc::channel;
 
 use crate::ws::client::{FsNotify, ServedFile};
+use content_hash::{content_hash, ContentHashes};
 use std::time::Duration;
 
+pub mod content_hash;
+
 pub struct FsWatcher {
     items: HashMap<PathBuf, ServedFile>,
+    hashes: ContentHashes,
     listeners: HashMap<usize, Recipient<FsNotify>>,
     rng: ThreadRng,
     watcher: Option<FsEventWatcher>,
@@ -26,6 +30,7 @@ impl Default for FsWatcher {
     fn default() -> Self {
         Self {
             items: HashMap::new(),
+            hashes: ContentHashes::default(),
             listeners: HashMap::new(),
             rng: rand::thread_rng(),
             watcher: None,
@@ -110,6 +115,11 @@ impl Handler<FsNotifyAll> for FsWatcher {
             "FsNotifyAll self.listeners count: [{}]",
             self.listeners.len()
         );
+        // drop events where the file contents did not actually change
+        if !self.hashes.update(&msg.pb, content_hash(&msg.pb).ok()) {
+            log::debug!("skipping, contents unchanged: {}", msg.pb.display());
+            return;
+        }
         for (_k, v) in self.listeners.iter() {
             if let Some(served) = self.items.get(&msg.pb) {
                 log::trace!("found `served` {:?}", served);
@@ -149,6 +159,9 @@ impl Handler<bs3_files::served::ServedFile> for FsWatcher {
         }
         let clone: ServedFile = msg.into();
         self.items.insert(clone.path.clone(), clone.clone());
+        if let Ok(hash) = content_hash(&clone.path) {
+            self.hashes.update(&clone.path, Some(hash));
+        }
         if let Some(watcher) = self.watcher.as_mut() {
             log::debug!("+++ adding item to watch {}", clone.path.display());
             let result = watcher.watch(&clone.path, RecursiveMode::NonRecursive);
@@ -0,0 +1,261 @@
+use actix_service::{Service, Transform};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::http::HeaderMap;
+use actix_web::{web, Error};
+use futures::future::{ok, Ready};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+///
+/// Set or remove response headers for request paths matching a glob, from
+/// `headers` in config. Every matching rule is applied, in order
+///
+/// eg: allow SharedArrayBuffer on every page
+///
+/// ```json
+/// {
+///     "match": "/**",
+///     "set": {
+///         "Cross-Origin-Opener-Policy": "same-origin",
+///         "Cross-Origin-Embedder-Policy": "require-corp"
+///     },
+///     "remove": ["X-Frame-Options"]
+/// }
+/// ```
+///
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
+pub struct HeaderRule {
+    #[serde(rename = "match")]
+    pub glob: String,
+    #[serde(default)]
+    pub set: BTreeMap<String, String>,
+    /// Removed before anything in `set` is added
+    #[serde(default)]
+    pub remove: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+struct HeaderChanges {
+    set: Vec<(HeaderName, HeaderValue)>,
+    remove: Vec<HeaderName>,
+}
+
+impl HeaderChanges {
+    fn new(rule: &HeaderRule) -> Result<Self, HeaderRuleError> {
+        let set = rule
+            .set
+            .iter()
+            .map(|(name, value)| Ok((header_name(name)?, header_value(value)?)))
+            .collect::<Result<_, HeaderRuleError>>()?;
+        let remove = rule
+            .remove
+            .iter()
+            .map(|name| header_name(name.as_str()))
+            .collect::<Result<_, HeaderRuleError>>()?;
+        Ok(Self { set, remove })
+    }
+    fn apply(&self, headers: &mut HeaderMap) {
+        for name in &self.remove {
+            headers.remove(name);
+        }
+        for (name, value) in &self.set {
+            headers.insert(name.clone(), value.clone());
+        }
+    }
+}
+
+fn header_name(name: &str) -> Result<HeaderName, HeaderRuleError> {
+    HeaderName::from_bytes(name.as_bytes())
+        .map_err(|_| HeaderRuleError::InvalidName(name.to_string()))
+}
+
+fn header_value(value: &str) -> Result<HeaderValue, HeaderRuleError> {
+    HeaderValue::from_str(value).map_err(|_| HeaderRuleError::InvalidValue(value.to_string()))
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum HeaderRuleError {
+    #[error("invalid header name `{0}`")]
+    InvalidName(String),
+    #[error("invalid header value `{0}`")]
+    InvalidValue(String),
+    #[error("invalid glob: {0}")]
+    InvalidGlob(String),
+}
+
+///
+/// The compiled form of all rules
+///
+#[derive(Debug, Clone)]
+pub struct HeaderRules {
+    set: GlobSet,
+    changes: Vec<HeaderChanges>,
+}
+
+impl Default for HeaderRules {
+    fn default() -> Self {
+        HeaderRules::new(&[])
+    }
+}
+
+impl HeaderRules {
+    pub fn new(rules: &[HeaderRule]) -> Self {
+        let mut builder = GlobSetBuilder::new();
+        let mut changes = vec![];
+        for rule in rules {
+            let compiled = Glob::new(&rule.glob)
+                .map_err(|e| HeaderRuleError::InvalidGlob(e.to_string()))
+                .and_then(|glob| Ok((glob, HeaderChanges::new(rule)?)));
+            match compiled {
+                Ok((glob, rule_changes)) => {
+                    builder.add(glob);
+                    changes.push(rule_changes);
+                }
+                Err(e) => log::error!("skipping header rule `{}`: {}", rule.glob, e),
+            }
+        }
+        let set = builder.build().unwrap_or_else(|e| {
+            log::error!("could not build header rules: {}", e);
+            GlobSet::empty()
+        });
+        Self { set, changes }
+    }
+    pub fn apply(&self, path: &str, headers: &mut HeaderMap) {
+        // `matches` gives indexes in the order the rules were added
+        for index in self.set.matches(path) {
+            if let Some(changes) = self.changes.get(index) {
+                changes.apply(headers);
+            }
+        }
+    }
+}
+
+///
+/// Applies the `HeaderRules` in app data to every response, whether it
+/// was served from disk, proxied or a 404
+///
+pub struct HeaderRulesMiddleware;
+
+impl<S: 'static> Transform<S> for HeaderRulesMiddleware
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse, Error = Error>,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse;
+    type Error = Error;
+    type InitError = ();
+    type Transform = HeaderRulesService<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(HeaderRulesService { service })
+    }
+}
+
+pub struct HeaderRulesService<S> {
+    service: S,
+}
+
+impl<S> Service for HeaderRulesService<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse, Error = Error> + 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<ServiceResponse, Error>>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let http_req = req.request().clone();
+        let srv_v = self.service.call(req);
+        Box::pin(async move {
+            // errors are turned into responses here, so that the rules apply to them too
+            let mut res = srv_v
+                .await
+                .unwrap_or_else(|e| ServiceResponse::from_err(e, http_req));
+            let req = res.request().clone();
+            if let Some(rules) = req.app_data::<web::Data<HeaderRules>>() {
+                rules.apply(req.path(), res.headers_mut());
+            }
+            Ok(res)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::error::ErrorBadGateway;
+    use actix_web::http::header::{CACHE_CONTROL, X_FRAME_OPTIONS};
+    use actix_web::test::{self, TestRequest};
+    use actix_web::{App, HttpResponse};
+    use futures::future::ready;
+
+    #[actix_rt::test]
+    async fn test_header_rules() -> anyhow::Result<()> {
+        let rules: Vec<HeaderRule> = serde_json::from_str(
+            r#"[
+                { "match": "/**", "set": { "Cache-Control": "no-store" } },
+                { "match": "/embed/**", "remove": ["x-frame-options"] },
+                { "match": "/{unclosed", "set": { "a": "b" } },
+                { "match": "/**", "set": { "bad name": "b" } }
+            ]"#,
+        )?;
+        let rules = HeaderRules::new(&rules);
+        assert_eq!(rules.changes.len(), 2);
+
+        let mut app =
+            test::init_service(App::new().data(rules).wrap(HeaderRulesMiddleware).service(
+                web::resource("/embed/page").to(|| async {
+                    HttpResponse::Ok()
+                        .header(X_FRAME_OPTIONS, "DENY")
+                        .header(CACHE_CONTROL, "max-age=3600")
+                        .finish()
+                }),
+            ))
+            .await;
+
+        let req = TestRequest::with_uri("/embed/page").to_request();
+        let res = test::call_service(&mut app, req).await;
+        assert_eq!(res.headers().get(CACHE_CONTROL).expect("set"), "no-store");
+        assert!(!res.headers().contains_key(X_FRAME_OPTIONS));
+
+        // 404s too
+        let req = TestRequest::with_uri("/missing").to_request();
+        let res = test::call_service(&mut app, req).await;
+        assert_eq!(res.status(), 404);
+        assert_eq!(res.headers().get(CACHE_CONTROL).expect("set"), "no-store");
+        Ok(())
+    }
+
+    #[actix_rt::test]
+    async fn test_inner_error() -> anyhow::Result<()> {
+        let rules: Vec<HeaderRule> = serde_json::from_str(
+            r#"[{ "match": "/**", "set": { "Cache-Control": "no-store" } }]"#,
+        )?;
+        let mut app = test::init_service(
+            App::new()
+                .data(HeaderRules::new(&rules))
+                .wrap_fn(|_req, _srv| {
+                    ready(Err::<ServiceResponse, _>(ErrorBadGateway(
+                        "upstream is down",
+                    )))
+                })
+                .wrap(HeaderRulesMiddleware),
+        )
+        .await;
+        let res = test::call_service(&mut app, TestRequest::default().to_request()).await;
+        assert_eq!(res.status(), 502);
+        assert_eq!(res.headers().get(CACHE_CONTROL).expect("set"), "no-store");
+        Ok(())
+    }
+}
@@ -1,4 +1,6 @@
 #[cfg(not(target_arch = "wasm32"))]
+pub mod api;
+#[cfg(not(target_arch = "wasm32"))]
 pub mod browser_sync;
 #[cfg(not(target_arch = "wasm32"))]
 pub mod cli;
@@ -9,8 +11,14 @@ pub mod config;
 #[cfg(not(target_arch = "wasm32"))]
 pub mod fs;
 #[cfg(not(target_arch = "wasm32"))]
+pub mod header_rules;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod reload_rules;
+#[cfg(not(target_arch = "wasm32"))]
 pub mod resp;
 #[cfg(not(target_arch = "wasm32"))]
+pub mod rewrite_rules;
+#[cfg(not(target_arch = "wasm32"))]
 pub mod start;
 #[cfg(not(target_arch = "wasm32"))]
 pub mod ws;
@@ -1,5 +1,6 @@
 use std::str::FromStr;
 
+use actix_web::dev::ResponseHead;
 use serde::{de, Deserialize, Deserializer, Serializer};
 
 pub mod proxy_resp_mod;
@@ -9,6 +10,17 @@ pub trait Proxy: Default {
     fn proxies(&self) -> Vec<ProxyTarget>;
 }
 
+///
+/// Added to the extensions of every response that came from a proxy target,
+/// so that `RespMod` guards can tell them apart from static files
+///
+#[derive(Debug, Copy, Clone)]
+pub struct Proxied;
+
+pub fn is_proxied(head: &ResponseHead) -> bool {
+    head.extensions().get::<Proxied>().is_some()
+}
+
 #[derive(Clone, Debug, Eq, PartialEq, serde::Serialize)]
 pub struct ProxyTarget {
     #[serde(serialize_with = "serialize_proxy")]
@@ -1,3 +1,4 @@
+use crate::resp::stream::{Replace, StreamMod};
 use crate::resp::RespMod;
 use actix_web::dev::{RequestHead, ResponseHead};
 use actix_web::http::HeaderMap;
@@ -8,8 +9,9 @@ pub struct ProxyResp {
     pub local_url: url::Url,
 }
 
-impl RespMod for ProxyResp {
-    fn process_str(&self, str: String) -> String {
+impl ProxyResp {
+    /// Absolute links to the proxied site are swapped for the local one
+    fn replacement(&self) -> (String, String) {
         let target = format!(
             "{}://{}",
             self.target_url.scheme(),
@@ -22,8 +24,19 @@ impl RespMod for ProxyResp {
             self.local_url.port().expect("local has a port")
         );
         log::trace!("replace [{}] with [{}]", target, local);
+        (target, local)
+    }
+}
+
+impl RespMod for ProxyResp {
+    fn process_str(&self, str: String) -> String {
+        let (target, local) = self.replacement();
         str.replace(&target, &local)
     }
+    fn stream(&self) -> Option<Box<dyn StreamMod>> {
+        let (target, local) = self.replacement();
+        Some(Box::new(Replace::new(target, local)))
+    }
     fn guard(&self, req_head: &RequestHead, res_head: &ResponseHead) -> bool {
         is_accept_html(&req_head.headers) && is_content_type_html(&res_head.headers)
     }
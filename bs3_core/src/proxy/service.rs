@@ -1,5 +1,8 @@
 #![allow(clippy::mutable_key_type)]
-use crate::proxy::ProxyTarget;
+use crate::proxy::{Proxied, ProxyTarget};
+use crate::ws::client::{ClientMsg, ErrorOverlayMsg};
+use crate::ws::server::{SendAll, WsServer};
+use actix::Addr;
 use actix_multi::service::MultiServiceFuture;
 use actix_web::dev::{ServiceRequest, ServiceResponse};
 use actix_web::{web, Error, HttpResponse};
@@ -9,9 +12,7 @@ use std::task::{Context, Poll};
 use actix_web::client::Client;
 
 use actix_web::http::header::HeaderName;
-use actix_web::http::header::{
-    CONNECTION, CONTENT_SECURITY_POLICY, HOST, REFERER, UPGRADE_INSECURE_REQUESTS,
-};
+use actix_web::http::header::{CONNECTION, HOST, REFERER, UPGRADE_INSECURE_REQUESTS};
 
 use std::collections::HashSet;
 use std::iter::FromIterator;
@@ -103,13 +104,29 @@ impl actix_service::Service for ProxyService {
 
             log::trace!("forwarding... {:?}", forwarded);
 
-            let mut res = forwarded.send_stream(body).await?;
+            let res = match forwarded.send_stream(body).await {
+                Ok(res) => res,
+                Err(e) => {
+                    log::error!("could not reach {}: {}", next_uri, e);
+                    // let every open browser know, rather than just the one making this request
+                    if let Some(srv) = req.app_data::<web::Data<Addr<WsServer>>>() {
+                        srv.do_send(SendAll {
+                            msg: ClientMsg::ErrorOverlay(ErrorOverlayMsg {
+                                title: String::from("Proxy error"),
+                                body: format!("Could not reach {}\n\n{}", next_uri, e),
+                                file: None,
+                                line: None,
+                            }),
+                        });
+                    }
+                    return Err(e.into());
+                }
+            };
             log::trace!("sent body stream");
             log::trace!("res = {:?}", res);
 
             let mut client_resp = HttpResponse::build(res.status());
-            let excluded_remote_headers: HashSet<HeaderName> =
-                HashSet::from_iter(vec![CONTENT_SECURITY_POLICY, CONNECTION]);
+            let excluded_remote_headers: HashSet<HeaderName> = HashSet::from_iter(vec![CONNECTION]);
 
             for (header_name, header_value) in res
                 .headers()
@@ -124,10 +141,92 @@ impl actix_service::Service for ProxyService {
                 client_resp.header(header_name.clone(), header_value.clone());
             }
 
-            let body = res.body().await?;
-            let res1 = client_resp.body(body);
+            // streamed rather than buffered, so that early-flushed or streamed HTML
+            // reaches the browser as the upstream sends it
+            let mut res1 = client_resp.streaming(res);
+            res1.extensions_mut().insert(Proxied);
             let res = ServiceResponse::new(req.clone(), res1);
             Ok(res)
         }))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::Collector;
+    use actix::Actor;
+    use actix_service::Service;
+    use actix_web::test::{self, TestRequest};
+    use actix_web::web::Bytes;
+    use actix_web::App;
+    use futures::{stream, StreamExt};
+    use std::str::FromStr;
+    use std::time::Duration;
+
+    #[actix_rt::test]
+    async fn test_streamed_upstream() {
+        // the end of the page only arrives after a (slow) data fetch
+        let upstream = test::start(|| {
+            App::new().default_service(web::to(|| async {
+                let head = stream::once(async { Ok::<_, Error>(Bytes::from("<html><body>")) });
+                let tail = stream::once(async {
+                    actix_rt::time::delay_for(Duration::from_secs(2)).await;
+                    Ok::<_, Error>(Bytes::from("<p>done</p></body></html>"))
+                });
+                HttpResponse::Ok()
+                    .content_type("text/html")
+                    .streaming(head.chain(tail))
+            }))
+        });
+        let mut proxy = ProxyService {
+            targets: vec![ProxyTarget::from_str(&upstream.url("/")).expect("valid target")],
+        };
+        let req = TestRequest::with_uri("/")
+            .data(Client::new())
+            .to_srv_request();
+        let mut res = proxy.call(req).await.expect("proxied");
+        let mut body = res.take_body();
+
+        let first = actix_rt::time::timeout(Duration::from_secs(1), body.next())
+            .await
+            .expect("the first chunk before the upstream finishes")
+            .expect("a chunk")
+            .expect("not an error");
+        assert_eq!(first, Bytes::from("<html><body>"));
+
+        let mut rest = vec![];
+        while let Some(chunk) = body.next().await {
+            rest.extend_from_slice(&chunk.expect("not an error"));
+        }
+        assert_eq!(rest, b"<p>done</p></body></html>");
+    }
+
+    #[actix_rt::test]
+    async fn test_upstream_down() {
+        let srv = WsServer::default().start();
+        let (_, browser) = Collector::connect(&srv).await;
+
+        // nothing listens on port 1
+        let mut proxy = ProxyService {
+            targets: vec![ProxyTarget::from_str("http://127.0.0.1:1").expect("valid target")],
+        };
+        let req = TestRequest::with_uri("/about.html")
+            .data(Client::new())
+            .data(srv.clone())
+            .to_srv_request();
+        assert!(proxy.call(req).await.is_err());
+
+        actix_rt::time::delay_for(Duration::from_millis(50)).await;
+        let overlay = browser
+            .received()
+            .into_iter()
+            .find_map(|msg| match msg {
+                ClientMsg::ErrorOverlay(overlay) => Some(overlay),
+                _ => None,
+            })
+            .expect("every browser is shown an overlay");
+        assert_eq!(overlay.title, "Proxy error");
+        assert!(overlay.body.contains("http://127.0.0.1:1/about.html"));
+    }
+}
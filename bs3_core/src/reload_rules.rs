@@ -0,0 +1,261 @@
+use crate::ws::client::{ClientMsg, ServedFile};
+use crate::ws::dep_graph::web_path;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+///
+/// Decide what connected browsers should do when a file changes.
+///
+/// Rules are matched against the web path of the changed file, the first
+/// match wins. User-provided rules are checked before the defaults
+///
+#[derive(Debug, Clone, Eq, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct ReloadRule {
+    #[serde(rename = "match")]
+    pub glob: String,
+    pub action: RuleAction,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleAction {
+    /// Hot-swap the file in place where possible (css, images)
+    Inject,
+    /// Reload the entire page
+    Reload,
+    /// Don't notify browsers at all
+    Ignore,
+}
+
+const IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "gif", "svg", "webp", "avif", "ico"];
+
+impl ReloadRule {
+    pub fn new(glob: impl Into<String>, action: RuleAction) -> Self {
+        Self {
+            glob: glob.into(),
+            action,
+        }
+    }
+    pub fn defaults() -> Vec<ReloadRule> {
+        let mut rules = vec![
+            ReloadRule::new("**/*.map", RuleAction::Ignore),
+            ReloadRule::new("**/*.css", RuleAction::Inject),
+        ];
+        rules.extend(
+            IMAGE_EXTENSIONS
+                .iter()
+                .map(|ext| ReloadRule::new(format!("**/*.{}", ext), RuleAction::Inject)),
+        );
+        rules
+    }
+}
+
+///
+/// Allow shorthand rules from the CLI in the form `action:glob`
+///
+/// eg: `--reload-rule ignore:/dist/**`
+///
+impl FromStr for ReloadRule {
+    type Err = ReloadRuleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut split = s.splitn(2, ':');
+        match (split.next(), split.next()) {
+            (Some(action), Some(glob)) if !glob.is_empty() => {
+                let action = RuleAction::from_str(action)?;
+                Glob::new(glob).map_err(|e| ReloadRuleError::InvalidGlob(e.to_string()))?;
+                Ok(ReloadRule::new(glob, action))
+            }
+            _ => Err(ReloadRuleError::Invalid(s.to_string())),
+        }
+    }
+}
+
+/// The same `action:glob` form that's accepted on the CLI
+impl fmt::Display for ReloadRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.action, self.glob)
+    }
+}
+
+impl FromStr for RuleAction {
+    type Err = ReloadRuleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "inject" => Ok(RuleAction::Inject),
+            "reload" => Ok(RuleAction::Reload),
+            "ignore" => Ok(RuleAction::Ignore),
+            _ => Err(ReloadRuleError::InvalidAction(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for RuleAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let as_str = match self {
+            RuleAction::Inject => "inject",
+            RuleAction::Reload => "reload",
+            RuleAction::Ignore => "ignore",
+        };
+        write!(f, "{}", as_str)
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ReloadRuleError {
+    #[error(
+        "invalid reload rule: {0}
+
+    Here's an example of a valid option
+
+    --reload-rule inject:**/*.css
+    "
+    )]
+    Invalid(String),
+    #[error("invalid reload rule action `{0}`, expected one of: inject, reload, ignore")]
+    InvalidAction(String),
+    #[error("invalid reload rule glob: {0}")]
+    InvalidGlob(String),
+}
+
+///
+/// The compiled form of all rules
+///
+#[derive(Debug, Clone)]
+pub struct ReloadRules {
+    set: GlobSet,
+    rules: Vec<ReloadRule>,
+}
+
+impl Default for ReloadRules {
+    fn default() -> Self {
+        ReloadRules::new(&[])
+    }
+}
+
+impl ReloadRules {
+    pub fn new(user_rules: &[ReloadRule]) -> Self {
+        let mut builder = GlobSetBuilder::new();
+        let mut rules = vec![];
+        for rule in user_rules.iter().cloned().chain(ReloadRule::defaults()) {
+            match Glob::new(&rule.glob) {
+                Ok(glob) => {
+                    builder.add(glob);
+                    rules.push(rule);
+                }
+                Err(e) => log::error!("skipping reload rule `{}`: {}", rule.glob, e),
+            }
+        }
+        let set = builder.build().unwrap_or_else(|e| {
+            log::error!("could not build reload rules: {}", e);
+            GlobSet::empty()
+        });
+        Self { set, rules }
+    }
+    /// Every active rule, in the order they're checked
+    pub fn summary(&self) -> Vec<String> {
+        self.rules.iter().map(|rule| rule.to_string()).collect()
+    }
+    /// Globs are matched against the `/`-prefixed web path, however the file was served
+    pub fn action(&self, item: &ServedFile) -> RuleAction {
+        self.set
+            .matches(web_path(&item.web_path))
+            .into_iter()
+            .min()
+            .and_then(|index| self.rules.get(index).map(|rule| rule.action))
+            .unwrap_or(RuleAction::Reload)
+    }
+    ///
+    /// Convert a changed file into the message browsers should receive,
+    /// `None` when the change should be ignored
+    ///
+    pub fn client_msg(&self, item: &ServedFile) -> Option<ClientMsg> {
+        let web_path = web_path(&item.web_path);
+        let ext = item
+            .web_path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_lowercase());
+        match (self.action(item), ext.as_deref()) {
+            (RuleAction::Ignore, _) => None,
+            (RuleAction::Inject, Some("css")) => Some(ClientMsg::InjectCss { web_path }),
+            (RuleAction::Inject, Some(ext)) if IMAGE_EXTENSIONS.contains(&ext) => {
+                Some(ClientMsg::ReplaceImage { web_path })
+            }
+            _ => Some(ClientMsg::Reload {
+                reason: format!("{} changed", web_path),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn served(web_path: &str) -> ServedFile {
+        ServedFile {
+            web_path: PathBuf::from(web_path),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_defaults() {
+        let rules = ReloadRules::default();
+        assert_eq!(rules.action(&served("/css/style.css")), RuleAction::Inject);
+        assert_eq!(rules.action(&served("/img/logo.png")), RuleAction::Inject);
+        assert_eq!(rules.action(&served("/app.js.map")), RuleAction::Ignore);
+        assert_eq!(rules.action(&served("/index.html")), RuleAction::Reload);
+    }
+
+    #[test]
+    fn test_user_rules_win() -> anyhow::Result<()> {
+        let rules = ReloadRules::new(&[
+            ReloadRule::from_str("reload:/critical/*.css")?,
+            ReloadRule::from_str("ignore:/dist/**")?,
+        ]);
+        assert!(matches!(
+            rules.client_msg(&served("/critical/above-fold.css")),
+            Some(ClientMsg::Reload { .. })
+        ));
+        assert!(matches!(
+            rules.client_msg(&served("/other.css")),
+            Some(ClientMsg::InjectCss { .. })
+        ));
+        assert!(rules.client_msg(&served("/dist/app.js")).is_none());
+        assert_eq!(rules.summary()[0], "reload:/critical/*.css");
+        Ok(())
+    }
+
+    #[test]
+    fn test_slashless_web_paths() -> anyhow::Result<()> {
+        let rules = ReloadRules::new(&[ReloadRule::from_str("ignore:/dist/**")?]);
+        assert_eq!(rules.action(&served("dist/app.js")), RuleAction::Ignore);
+        assert!(rules.client_msg(&served("dist/app.js")).is_none());
+        assert!(matches!(
+            rules.client_msg(&served("css/style.css")),
+            Some(ClientMsg::InjectCss { web_path }) if web_path == "/css/style.css"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_str_err() {
+        assert!(ReloadRule::from_str("inject").is_err());
+        assert!(ReloadRule::from_str("unknown:**/*.css").is_err());
+        assert!(ReloadRule::from_str("inject:").is_err());
+    }
+
+    #[test]
+    fn test_deserialize() -> anyhow::Result<()> {
+        let input = r#"{"match": "**/*.scss", "action": "ignore"}"#;
+        let rule: ReloadRule = serde_json::from_str(input)?;
+        assert_eq!(rule, ReloadRule::new("**/*.scss", RuleAction::Ignore));
+        Ok(())
+    }
+}
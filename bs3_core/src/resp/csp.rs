@@ -0,0 +1,321 @@
+use actix_web::dev::ResponseHead;
+use actix_web::http::header::{
+    HeaderValue, CONTENT_SECURITY_POLICY, CONTENT_SECURITY_POLICY_REPORT_ONLY,
+};
+use rand::Rng;
+use regex::Regex;
+
+/// Where `<script>` elements are checked, most specific first
+const SCRIPT_DIRECTIVES: &[&str] = &["script-src-elem", "script-src"];
+/// Where `eval()` & `new Function()` are checked
+const EVAL_DIRECTIVES: &[&str] = &["script-src"];
+/// Where websocket & `fetch()` connections are checked
+const CONNECT_DIRECTIVES: &[&str] = &["connect-src"];
+
+lazy_static::lazy_static! {
+    static ref META_HTTP_EQUIV: Regex =
+        Regex::new(r#"(?i)\bhttp-equiv\s*=\s*["']?content-security-policy\b"#)
+            .expect("valid http-equiv pattern");
+    static ref META_CONTENT: Regex = Regex::new(r#"(?i)\bcontent\s*=\s*(?:"([^"]*)"|'([^']*)')"#)
+        .expect("valid content pattern");
+}
+
+///
+/// What the injected bs3 client needs a Content-Security-Policy to allow, for
+/// a single response: its `<script>` tag, with `nonce`, and its connections
+/// back to bs3 on the same host, over websockets or the SSE fallback
+///
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct CspAllow {
+    pub nonce: String,
+    pub connect: Vec<String>,
+    /// `'unsafe-eval'`, which `--allow-eval` needs to run code & `--hmr` to re-import modules
+    pub eval: bool,
+}
+
+impl CspAllow {
+    ///
+    /// A new random nonce. `host` is the `Host` the page was requested from, which
+    /// the client connects back to
+    ///
+    pub fn new(host: Option<&str>) -> Self {
+        let bytes: [u8; 16] = rand::thread_rng().gen();
+        let nonce = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        let mut connect = vec![String::from("'self'")];
+        if let Some(host) = host {
+            connect.push(format!("ws://{}", host));
+            connect.push(format!("wss://{}", host));
+        }
+        Self {
+            nonce,
+            connect,
+            eval: false,
+        }
+    }
+
+    pub fn with_eval(mut self, eval: bool) -> Self {
+        self.eval = eval;
+        self
+    }
+
+    ///
+    /// Rewrite every `Content-Security-Policy` (and report-only) header
+    ///
+    pub fn apply_headers(&self, head: &mut ResponseHead) {
+        for name in &[CONTENT_SECURITY_POLICY, CONTENT_SECURITY_POLICY_REPORT_ONLY] {
+            let values: Vec<String> = head
+                .headers
+                .get_all(name)
+                .filter_map(|hv| hv.to_str().ok())
+                .map(|policy| self.apply(policy))
+                .collect();
+            if values.is_empty() {
+                continue;
+            }
+            head.headers.remove(name);
+            for value in values {
+                match HeaderValue::from_str(&value) {
+                    Ok(hv) => head.headers.append(name.clone(), hv),
+                    Err(e) => log::error!("could not set {}: {}", name, e),
+                }
+            }
+        }
+    }
+
+    ///
+    /// Rewrite a `<meta http-equiv="Content-Security-Policy" content="...">` tag,
+    /// any other tag is returned as-is
+    ///
+    pub fn apply_meta(&self, tag: &str) -> String {
+        if !META_HTTP_EQUIV.is_match(tag) {
+            return tag.to_string();
+        }
+        let caps = match META_CONTENT.captures(tag) {
+            Some(caps) => caps,
+            None => return tag.to_string(),
+        };
+        let (value, single_quoted) = match (caps.get(1), caps.get(2)) {
+            (Some(value), _) => (value, false),
+            (_, Some(value)) => (value, true),
+            _ => return tag.to_string(),
+        };
+        let mut policy = self.apply(value.as_str());
+        if single_quoted {
+            policy = policy.replace('\'', "&#39;");
+        }
+        format!("{}{}{}", &tag[..value.start()], policy, &tag[value.end()..])
+    }
+
+    ///
+    /// Allow the bs3 client in a serialized policy. A header can hold several
+    /// comma-separated policies, and each of them is enforced
+    ///
+    pub fn apply(&self, policies: &str) -> String {
+        policies
+            .split(',')
+            .map(|policy| {
+                let mut policy = Policy::parse(policy);
+                let nonce = format!("'nonce-{}'", self.nonce);
+                policy.allow(SCRIPT_DIRECTIVES, |sources| {
+                    // a nonce would disable 'unsafe-inline', breaking the page's own inline scripts
+                    let unsafe_inline = sources.iter().any(|s| s == "'unsafe-inline'")
+                        && !sources.iter().any(|s| is_nonce_or_hash(s));
+                    if unsafe_inline {
+                        vec![String::from("'self'")]
+                    } else {
+                        vec![nonce.clone()]
+                    }
+                });
+                if self.eval {
+                    policy.allow(EVAL_DIRECTIVES, |_| vec![String::from("'unsafe-eval'")]);
+                }
+                policy.allow(CONNECT_DIRECTIVES, |_| self.connect.clone());
+                policy.to_string()
+            })
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+}
+
+fn is_nonce_or_hash(source: &str) -> bool {
+    ["'nonce-", "'sha256-", "'sha384-", "'sha512-"]
+        .iter()
+        .any(|prefix| source.to_ascii_lowercase().starts_with(prefix))
+}
+
+///
+/// A single policy, eg: `default-src 'self'; script-src 'nonce-abc'`
+///
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Policy {
+    directives: Vec<(String, Vec<String>)>,
+}
+
+impl Policy {
+    fn parse(policy: &str) -> Self {
+        let directives = policy
+            .split(';')
+            .filter_map(|directive| {
+                let mut parts = directive.split_whitespace();
+                let name = parts.next()?.to_ascii_lowercase();
+                Some((name, parts.map(String::from).collect()))
+            })
+            .collect();
+        Self { directives }
+    }
+
+    ///
+    /// Add sources to each of `names` that's present. When none are, the sources are added
+    /// to a copy of `default-src`, since that's what they fall back to.
+    /// Without `default-src` either, everything is already allowed
+    ///
+    fn allow<F>(&mut self, names: &[&str], sources_for: F)
+    where
+        F: Fn(&[String]) -> Vec<String>,
+    {
+        let mut found = false;
+        for (name, sources) in self.directives.iter_mut() {
+            if names.contains(&name.as_str()) {
+                found = true;
+                let extra = sources_for(sources);
+                add_sources(sources, extra);
+            }
+        }
+        if found {
+            return;
+        }
+        let default = self
+            .directives
+            .iter()
+            .find(|(name, _)| name == "default-src")
+            .map(|(_, sources)| sources.clone());
+        if let (Some(mut sources), Some(name)) = (default, names.last()) {
+            let extra = sources_for(&sources);
+            add_sources(&mut sources, extra);
+            self.directives.push((name.to_string(), sources));
+        }
+    }
+}
+
+fn add_sources(sources: &mut Vec<String>, extra: Vec<String>) {
+    // 'none' can't be combined with anything else
+    sources.retain(|source| source != "'none'");
+    for source in extra {
+        if !sources.contains(&source) {
+            sources.push(source);
+        }
+    }
+}
+
+impl std::fmt::Display for Policy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let directives: Vec<String> = self
+            .directives
+            .iter()
+            .map(|(name, sources)| {
+                std::iter::once(name.as_str())
+                    .chain(sources.iter().map(String::as_str))
+                    .collect::<Vec<&str>>()
+                    .join(" ")
+            })
+            .collect();
+        write!(f, "{}", directives.join("; "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+
+    fn allow() -> CspAllow {
+        CspAllow {
+            nonce: String::from("abc"),
+            connect: vec![String::from("'self'"), String::from("ws://localhost:8090")],
+            eval: false,
+        }
+    }
+
+    #[test]
+    fn test_apply() {
+        assert_eq!(
+            allow().apply("script-src 'self' https://cdn.example.com; connect-src 'none'"),
+            "script-src 'self' https://cdn.example.com 'nonce-abc'; connect-src 'self' ws://localhost:8090"
+        );
+        // falls back to default-src
+        assert_eq!(
+            allow().apply("default-src 'self'; img-src *"),
+            "default-src 'self'; img-src *; script-src 'self' 'nonce-abc'; connect-src 'self' ws://localhost:8090"
+        );
+        // nothing restricts scripts or connections
+        assert_eq!(allow().apply("img-src 'self'"), "img-src 'self'");
+        // a nonce would turn off 'unsafe-inline'
+        assert_eq!(
+            allow().apply("script-src 'unsafe-inline'"),
+            "script-src 'unsafe-inline' 'self'"
+        );
+        assert_eq!(
+            allow().apply("script-src 'unsafe-inline' 'nonce-xyz'"),
+            "script-src 'unsafe-inline' 'nonce-xyz' 'nonce-abc'"
+        );
+        // every policy in the list
+        assert_eq!(
+            allow().apply("script-src 'none', script-src-elem 'strict-dynamic'"),
+            "script-src 'nonce-abc', script-src-elem 'strict-dynamic' 'nonce-abc'"
+        );
+    }
+
+    #[test]
+    fn test_apply_eval() {
+        let allow = allow().with_eval(true);
+        assert_eq!(
+            allow.apply("script-src 'self'"),
+            "script-src 'self' 'nonce-abc' 'unsafe-eval'"
+        );
+        assert_eq!(
+            allow.apply("default-src 'self'"),
+            "default-src 'self'; script-src 'self' 'nonce-abc' 'unsafe-eval'; connect-src 'self' ws://localhost:8090"
+        );
+        // `<script>` elements are checked against script-src-elem, eval against script-src
+        assert_eq!(
+            allow.apply("script-src-elem 'self'; script-src 'none'"),
+            "script-src-elem 'self' 'nonce-abc'; script-src 'nonce-abc' 'unsafe-eval'"
+        );
+        assert_eq!(allow.apply("img-src 'self'"), "img-src 'self'");
+    }
+
+    #[test]
+    fn test_apply_headers() {
+        let mut head = ResponseHead::new(StatusCode::OK);
+        head.headers.insert(
+            CONTENT_SECURITY_POLICY,
+            HeaderValue::from_static("script-src 'none'"),
+        );
+        allow().apply_headers(&mut head);
+        assert_eq!(
+            head.headers.get(CONTENT_SECURITY_POLICY).expect("csp"),
+            "script-src 'nonce-abc'"
+        );
+        assert!(!head
+            .headers
+            .contains_key(CONTENT_SECURITY_POLICY_REPORT_ONLY));
+    }
+
+    #[test]
+    fn test_apply_meta() {
+        assert_eq!(
+            allow().apply_meta(
+                r#"<meta http-equiv="Content-Security-Policy" content="script-src 'self'">"#
+            ),
+            r#"<meta http-equiv="Content-Security-Policy" content="script-src 'self' 'nonce-abc'">"#
+        );
+        assert_eq!(
+            allow().apply_meta(
+                r#"<META CONTENT='script-src https:' HTTP-EQUIV=content-security-policy>"#
+            ),
+            r#"<META CONTENT='script-src https: &#39;nonce-abc&#39;' HTTP-EQUIV=content-security-policy>"#
+        );
+        let other = r#"<meta name="description" content="script-src">"#;
+        assert_eq!(allow().apply_meta(other), other);
+    }
+}
@@ -0,0 +1,181 @@
+use actix_web::http::header::CONTENT_ENCODING;
+use actix_web::http::HeaderMap;
+use brotli2::read::BrotliDecoder;
+use brotli2::write::BrotliEncoder;
+use flate2::read::{GzDecoder, ZlibDecoder};
+use flate2::write::{GzEncoder, ZlibEncoder};
+use flate2::Compression;
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+
+/// Same level as actix-web's own `Compress` middleware
+const BROTLI_LEVEL: u32 = 3;
+/// zstd's default
+const ZSTD_LEVEL: i32 = 3;
+
+///
+/// A single content-coding, as found in a `Content-Encoding` header
+///
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Coding {
+    Gzip,
+    /// zlib-wrapped deflate, as required by RFC 7230
+    Deflate,
+    Br,
+    Zstd,
+}
+
+impl FromStr for Coding {
+    type Err = EncodingError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "gzip" | "x-gzip" => Ok(Coding::Gzip),
+            "deflate" => Ok(Coding::Deflate),
+            "br" => Ok(Coding::Br),
+            "zstd" => Ok(Coding::Zstd),
+            other => Err(EncodingError::Unsupported(other.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum EncodingError {
+    #[error("unsupported content-encoding `{0}`")]
+    Unsupported(String),
+    #[error("content-encoding is not valid ascii")]
+    InvalidHeader,
+}
+
+///
+/// Every coding applied to a response, in the order they were applied.
+///
+/// eg: `Content-Encoding: gzip, br` was gzipped first, then brotli compressed.
+/// `identity` doesn't change anything so it's skipped
+///
+pub fn codings(headers: &HeaderMap) -> Result<Vec<Coding>, EncodingError> {
+    let mut codings = vec![];
+    for value in headers.get_all(CONTENT_ENCODING) {
+        let value = value.to_str().map_err(|_| EncodingError::InvalidHeader)?;
+        for coding in value.split(',').map(str::trim) {
+            if coding.is_empty() || coding.eq_ignore_ascii_case("identity") {
+                continue;
+            }
+            codings.push(coding.parse()?);
+        }
+    }
+    Ok(codings)
+}
+
+/// Undo every coding, last applied first
+pub fn decode(bytes: &[u8], codings: &[Coding]) -> io::Result<Vec<u8>> {
+    let mut output = bytes.to_vec();
+    for coding in codings.iter().rev() {
+        output = decode_one(&output, *coding)?;
+    }
+    Ok(output)
+}
+
+/// Re-apply every coding, in the original order
+pub fn encode(bytes: &[u8], codings: &[Coding]) -> io::Result<Vec<u8>> {
+    let mut output = bytes.to_vec();
+    for coding in codings {
+        output = encode_one(&output, *coding)?;
+    }
+    Ok(output)
+}
+
+fn decode_one(bytes: &[u8], coding: Coding) -> io::Result<Vec<u8>> {
+    let mut output = Vec::new();
+    match coding {
+        Coding::Gzip => {
+            GzDecoder::new(bytes).read_to_end(&mut output)?;
+        }
+        Coding::Deflate => {
+            ZlibDecoder::new(bytes).read_to_end(&mut output)?;
+        }
+        Coding::Br => {
+            BrotliDecoder::new(bytes).read_to_end(&mut output)?;
+        }
+        Coding::Zstd => output = zstd::stream::decode_all(bytes)?,
+    }
+    Ok(output)
+}
+
+fn encode_one(bytes: &[u8], coding: Coding) -> io::Result<Vec<u8>> {
+    match coding {
+        Coding::Gzip => {
+            let mut e = GzEncoder::new(Vec::new(), Compression::default());
+            e.write_all(bytes)?;
+            e.finish()
+        }
+        Coding::Deflate => {
+            let mut e = ZlibEncoder::new(Vec::new(), Compression::default());
+            e.write_all(bytes)?;
+            e.finish()
+        }
+        Coding::Br => {
+            let mut e = BrotliEncoder::new(Vec::new(), BROTLI_LEVEL);
+            e.write_all(bytes)?;
+            e.finish()
+        }
+        Coding::Zstd => zstd::stream::encode_all(bytes, ZSTD_LEVEL),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::HeaderValue;
+
+    const HTML: &str = "<html><body><p>hello world</p></body></html>";
+
+    #[test]
+    fn test_roundtrip() -> anyhow::Result<()> {
+        for coding in &[Coding::Gzip, Coding::Deflate, Coding::Br, Coding::Zstd] {
+            let encoded = encode(HTML.as_bytes(), &[*coding])?;
+            assert_ne!(encoded, HTML.as_bytes(), "{:?}", coding);
+            assert_eq!(
+                decode(&encoded, &[*coding])?,
+                HTML.as_bytes(),
+                "{:?}",
+                coding
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_multiple_codings() -> anyhow::Result<()> {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            CONTENT_ENCODING,
+            HeaderValue::from_static("gzip, identity, BR"),
+        );
+        let codings = codings(&headers)?;
+        assert_eq!(codings, vec![Coding::Gzip, Coding::Br]);
+
+        let br_last = encode_one(&encode_one(HTML.as_bytes(), Coding::Gzip)?, Coding::Br)?;
+        assert_eq!(decode(&br_last, &codings)?, HTML.as_bytes());
+        assert_eq!(encode(HTML.as_bytes(), &codings)?, br_last);
+        Ok(())
+    }
+
+    #[test]
+    fn test_unsupported() {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_ENCODING, HeaderValue::from_static("compress"));
+        assert!(matches!(
+            codings(&headers),
+            Err(EncodingError::Unsupported(coding)) if coding == "compress"
+        ));
+        assert_eq!(codings(&HeaderMap::new()).ok(), Some(vec![]));
+    }
+
+    #[test]
+    fn test_corrupt() {
+        for coding in &[Coding::Gzip, Coding::Deflate, Coding::Br, Coding::Zstd] {
+            assert!(decode(HTML.as_bytes(), &[*coding]).is_err(), "{:?}", coding);
+        }
+    }
+}
@@ -1,3 +1,7 @@
+pub mod csp;
+pub mod encoding;
+pub mod stream;
+
 use std::future::Future;
 
 use std::pin::Pin;
@@ -8,19 +12,21 @@ use actix_web::{
     body::ResponseBody,
     dev::{RequestHead, ResponseHead, ServiceRequest, ServiceResponse},
     web::{self, Bytes, BytesMut},
-    Error,
+    Error, HttpResponse,
 };
 
-use actix_web::dev::Body;
-use actix_web::http::header::CONTENT_ENCODING;
-use actix_web::http::{ContentEncoding, HeaderValue};
-use flate2::read::GzDecoder;
-use flate2::write::GzEncoder;
-use flate2::Compression;
+use actix_web::dev::{Body, BodySize, MessageBody};
+use actix_web::http::header::{CONTENT_LENGTH, ETAG, LAST_MODIFIED};
+use actix_web::http::{HeaderMap, HeaderValue, Method, StatusCode};
+use encoding::Coding;
 use futures::future::{ok, Ready};
 use futures_util::StreamExt;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 use std::io;
-use std::io::{Read, Write};
+use std::ops::Deref;
+use std::panic::{self, AssertUnwindSafe};
+use stream::{RewriteBody, StreamMod};
 
 ///
 /// Response Modifications
@@ -40,15 +46,58 @@ pub trait RespMod {
         resp
     }
     ///
+    /// Rewrite the body chunk by chunk as it streams through, instead of buffering it.
+    ///
+    /// Mods that need the entire document return `None` (the default), and
+    /// `process_str` is used instead
+    ///
+    fn stream(&self) -> Option<Box<dyn StreamMod>> {
+        None
+    }
+    ///
     /// To prevent buffering/modifications on all requests,
     /// you need to implement this guard
     ///
     fn guard(&self, req_head: &RequestHead, res_head: &ResponseHead) -> bool;
+    ///
+    /// Change the head of a response that passed `guard`, before its body is read.
+    ///
+    /// Returning a `RespMod` replaces this one for the body of this response only,
+    /// so that it can carry per-response state, eg: a CSP nonce
+    ///
+    fn process_head(
+        &self,
+        _req_head: &RequestHead,
+        _res_head: &mut ResponseHead,
+    ) -> Option<Box<dyn RespMod>> {
+        None
+    }
 }
 
 pub trait RespModDataTrait {
     fn indexes(&self, req_head: &RequestHead, res_head: &ResponseHead) -> Vec<usize>;
-    fn process_str(&self, input: String, indexes: &[usize]) -> String;
+    /// Give each of `indexes` a chance to change the head, in order
+    fn process_head(
+        &self,
+        req_head: &RequestHead,
+        res_head: &mut ResponseHead,
+        indexes: &[usize],
+    ) -> ActiveMods<'_>;
+}
+
+///
+/// Why a response was passed through unmodified
+///
+#[derive(Debug, thiserror::Error)]
+pub enum RespModError {
+    #[error("could not decode the body: {0}")]
+    Decode(io::Error),
+    #[error("the decoded body is not utf8: {0}")]
+    NotUtf8(std::str::Utf8Error),
+    #[error("RespMod `{0}` panicked")]
+    Panicked(String),
+    #[error("could not re-encode the body: {0}")]
+    Encode(io::Error),
 }
 
 pub struct RespModData {
@@ -70,13 +119,63 @@ impl RespModDataTrait for RespModData {
             .collect()
     }
 
-    fn process_str(&self, input: String, indexes: &[usize]) -> String {
-        indexes.iter().fold(input, |acc, index| {
-            let item = self.items.get(*index).expect("guarded");
-            log::debug!("processing [{}] {}", index, item.name());
-            item.process_str(acc)
+    fn process_head(
+        &self,
+        req_head: &RequestHead,
+        res_head: &mut ResponseHead,
+        indexes: &[usize],
+    ) -> ActiveMods<'_> {
+        let items = indexes
+            .iter()
+            .filter_map(|index| self.items.get(*index))
+            .map(|item| match item.process_head(req_head, res_head) {
+                Some(replaced) => ActiveMod::Owned(replaced),
+                None => ActiveMod::Shared(item.as_ref()),
+            })
+            .collect();
+        ActiveMods(items)
+    }
+}
+
+///
+/// A `RespMod` as applied to a single response: either the shared one, or
+/// the replacement it returned from `process_head`
+///
+pub enum ActiveMod<'a> {
+    Shared(&'a dyn RespMod),
+    Owned(Box<dyn RespMod>),
+}
+
+impl<'a> Deref for ActiveMod<'a> {
+    type Target = dyn RespMod + 'a;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            ActiveMod::Shared(item) => *item,
+            ActiveMod::Owned(item) => item.as_ref(),
+        }
+    }
+}
+
+/// Every `RespMod` applied to a single response, in order
+pub struct ActiveMods<'a>(Vec<ActiveMod<'a>>);
+
+impl<'a> ActiveMods<'a> {
+    pub fn process_str(&self, input: String) -> Result<String, RespModError> {
+        self.0.iter().try_fold(input, |acc, item| {
+            log::debug!("processing {}", item.name());
+            panic::catch_unwind(AssertUnwindSafe(|| item.process_str(acc)))
+                .map_err(|_| RespModError::Panicked(item.name()))
         })
     }
+
+    /// `Some` only when every mod can stream, each named after its `RespMod`
+    pub fn streams(&self) -> Option<Vec<(String, Box<dyn StreamMod>)>> {
+        self.0
+            .iter()
+            .map(|item| item.stream().map(|stream| (item.name(), stream)))
+            .collect()
+    }
 }
 
 pub struct RespModMiddleware;
@@ -119,149 +218,452 @@ where
         let srv_v = self.service.call(req);
 
         Box::pin(async move {
-            let res = srv_v.await;
-            match res {
-                Ok(res) => {
-                    let mut res: ServiceResponse = res;
-                    let req = res.request().clone();
-                    let uri_string = req.uri().to_string();
-
-                    let head = req.head();
-                    let response = res.response();
-
-                    //
-                    // These are the transformed registered in config
-                    //
-                    let transforms = req
-                        .app_data::<web::Data<RespModData>>()
-                        .map(|t| t.get_ref());
-
-                    //
-                    // 'indexes' are the transforms that should be applied to the body.
-                    // eg: if 'indexes' is [0, 1] -> this means 2 transforms will be applied to this response
-                    //
-                    let indexes: Vec<usize> = transforms
-                        .map(|trans| trans.indexes(head, response.head()))
-                        .unwrap_or_else(Vec::new);
-
-                    log::debug!("indexes to process = {:?}", indexes);
-
-                    //
-                    // Early return if no-one wants to edit this response
-                    //
-                    if indexes.is_empty() {
-                        return Ok(res);
-                    }
-
-                    let mut body = BytesMut::new();
-                    let mut stream = res.take_body();
-
-                    while let Some(chunk) = stream.next().await {
-                        log::debug!("++ chunk from buffered response body");
-                        body.extend_from_slice(&chunk?);
-                    }
-
-                    //
-                    // From the "content-encoding" header, determine if the response
-                    // requires de-coding before we can modify it
-                    //
-                    let encoding = res
-                        .response()
-                        .headers()
-                        .get("content-encoding")
-                        .and_then(|val| val.to_str().ok())
-                        .map(ContentEncoding::from)
-                        .unwrap_or(ContentEncoding::Identity);
-
-                    log::debug!("handling encoding: {:?}", encoding);
-
-                    //
-                    // decode the bytes if we can
-                    //
-                    let decoded_bytes: Bytes = match encoding {
-                        ContentEncoding::Gzip => {
-                            log::trace!("decoding a buffered gzip response");
-                            let decoded = decode_gzip(body.to_vec()).expect("decode");
-                            Bytes::from(decoded)
-                        }
-                        _ => Bytes::from(body),
-                    };
-
-                    //
-                    // Process each transform on the content
-                    //
-                    process_buffered_body(decoded_bytes, uri_string, transforms, &indexes)
-                        //
-                        // Whether or not to re-encode the response, based on whether the original was
-                        //
-                        .map(|processes_bytes| match encoding {
-                            ContentEncoding::Gzip => {
-                                let encoded =
-                                    encode_gzip(processes_bytes.to_vec()).expect("gzip encode");
-                                Bytes::from(encoded)
-                            }
-                            _ => processes_bytes,
-                        })
-                        //
-                        // Now with either modified bytes or original, we can re-send them
-                        //
-                        .map(|output_bytes| {
-                            res.map_body(|head, _body| {
-                                head.headers_mut().insert(
-                                    CONTENT_ENCODING,
-                                    HeaderValue::from_str(encoding.as_str())
-                                        .expect("creation of this header never fails"),
-                                );
-                                ResponseBody::Body(Body::Bytes(output_bytes))
-                            })
-                        })
+            // errors from inner services are sent as normal error responses
+            let mut res: ServiceResponse = srv_v.await?;
+            let req = res.request().clone();
+            let uri_string = req.uri().to_string();
+
+            let head = req.head();
+            let response = res.response();
+
+            if !has_body(head, response) {
+                return Ok(res);
+            }
+
+            //
+            // These are the transformed registered in config
+            //
+            let transforms = match req.app_data::<web::Data<RespModData>>() {
+                Some(transforms) => transforms.get_ref(),
+                None => return Ok(res),
+            };
+
+            //
+            // 'indexes' are the transforms that should be applied to the body.
+            // eg: if 'indexes' is [0, 1] -> this means 2 transforms will be applied to this response
+            //
+            let indexes: Vec<usize> = transforms.indexes(head, response.head());
+
+            log::debug!("indexes to process = {:?}", indexes);
+
+            //
+            // Early return if no-one wants to edit this response
+            //
+            if indexes.is_empty() {
+                return Ok(res);
+            }
+
+            //
+            // From the "content-encoding" header, determine if the response
+            // requires de-coding before we can modify it
+            //
+            let codings = match encoding::codings(res.response().headers()) {
+                Ok(codings) => codings,
+                Err(e) => {
+                    log::debug!("not modifying `{}`: {}", uri_string, e);
+                    return Ok(res);
+                }
+            };
+
+            log::debug!("handling encoding: {:?}", codings);
+
+            //
+            // Each mod may change the headers before the body is read, eg: to allow
+            // the injected script through a Content-Security-Policy
+            //
+            let mods = transforms.process_head(head, res.response_mut().head_mut(), &indexes);
+
+            //
+            // Uncompressed bodies can be rewritten as they stream through, as long as
+            // every mod supports it. This keeps time-to-first-byte for large or
+            // streamed HTML
+            //
+            let identity = codings.is_empty();
+            if let (true, Some(streams)) = (identity, mods.streams()) {
+                log::debug!("streaming indexes {:?} for `{}`", indexes, uri_string);
+                return Ok(res.map_body(|head, body| {
+                    // the new body isn't known until it's been sent
+                    replace_validators(head.headers_mut(), None);
+                    ResponseBody::Body(Body::from_message(RewriteBody::new(body, streams)))
+                }));
+            }
+
+            let mut body = BytesMut::new();
+            let mut stream = res.take_body();
+
+            while let Some(chunk) = stream.next().await {
+                log::debug!("++ chunk from buffered response body");
+                body.extend_from_slice(&chunk?);
+            }
+
+            //
+            // Decode, process each transform on the content & re-encode. The
+            // content-encoding header is unchanged, since the same codings are
+            // re-applied. If anything goes wrong, the original bytes are sent instead
+            //
+            let original = body.freeze();
+            if original.is_empty() {
+                return Ok(res.map_body(|_head, _body| ResponseBody::Body(Body::Bytes(original))));
+            }
+            match modify_buffered(&original, &codings, &mods) {
+                Ok(modified) => Ok(res.map_body(|head, _body| {
+                    replace_validators(head.headers_mut(), Some(&modified));
+                    ResponseBody::Body(Body::Bytes(modified))
+                })),
+                Err(e) => {
+                    log::error!("passing `{}` through unmodified: {}", uri_string, e);
+                    Ok(res.map_body(|_head, _body| ResponseBody::Body(Body::Bytes(original))))
                 }
-                Err(..) => todo!(),
             }
         })
     }
 }
 
+///
+/// HEAD requests, 204 & 304 responses and empty bodies have nothing to modify,
+/// and must stay empty - mods such as the script injector would add to them
+///
+fn has_body(req_head: &RequestHead, res: &HttpResponse) -> bool {
+    req_head.method != Method::HEAD
+        && !matches!(
+            res.status(),
+            StatusCode::NO_CONTENT | StatusCode::NOT_MODIFIED
+        )
+        && !matches!(
+            res.body().size(),
+            BodySize::None | BodySize::Empty | BodySize::Sized(0)
+        )
+}
+
+///
+/// Validators & digests from the original response describe different bytes
+/// to the ones being sent, and would let browsers keep serving a cached page
+/// that's missing the modifications.
+///
+/// They're removed, and a weak ETag is derived from the new body when it's known
+///
+fn replace_validators(headers: &mut HeaderMap, body: Option<&[u8]>) {
+    for name in &[ETAG, LAST_MODIFIED, CONTENT_LENGTH] {
+        headers.remove(name);
+    }
+    for name in &["content-md5", "digest"] {
+        headers.remove(*name);
+    }
+    if let Some(body) = body {
+        let mut hasher = DefaultHasher::new();
+        body.hash(&mut hasher);
+        let etag = format!(r#"W/"{:x}-{:x}""#, body.len(), hasher.finish());
+        headers.insert(
+            ETAG,
+            HeaderValue::from_str(&etag).expect("hex is a valid header value"),
+        );
+    }
+}
+
 ///
 /// Process the entire buffered body in 1 go, this avoids trying to match over
 /// chunked responses etc
 ///
-fn process_buffered_body(
-    bytes: Bytes,
-    uri: String,
-    transforms: Option<&RespModData>,
-    indexes: &[usize],
-) -> Result<Bytes, Error> {
-    let to_process = std::str::from_utf8(&bytes);
-    match to_process {
-        Ok(str) => {
-            let string = String::from(str);
-            if !indexes.is_empty() {
-                log::debug!("processing indexes {:?} for `{}`", indexes, uri);
-                let next = transforms
-                    .map(|trans| trans.process_str(string.clone(), indexes))
-                    .unwrap_or_else(String::new);
-                return Ok(Bytes::from(next));
-            }
-            log::debug!("NOT processing indexes {:?} for `{}`", indexes, uri);
-            Ok(Bytes::from(string))
+fn modify_buffered(
+    bytes: &[u8],
+    codings: &[Coding],
+    mods: &ActiveMods,
+) -> Result<Bytes, RespModError> {
+    let decoded = encoding::decode(bytes, codings).map_err(RespModError::Decode)?;
+    let text = String::from_utf8(decoded).map_err(|e| RespModError::NotUtf8(e.utf8_error()))?;
+    let processed = mods.process_str(text)?;
+    let encoded = encoding::encode(processed.as_bytes(), codings).map_err(RespModError::Encode)?;
+    Ok(Bytes::from(encoded))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::script::Script;
+    use actix_web::error::ErrorBadGateway;
+    use actix_web::http::header::{
+        ACCEPT, CONTENT_ENCODING, CONTENT_SECURITY_POLICY, CONTENT_TYPE,
+    };
+    use actix_web::http::{HeaderName, StatusCode};
+    use actix_web::test::{self, TestRequest};
+    use actix_web::{App, HttpResponse};
+    use bs3_files::Files;
+    use futures::future::ready;
+
+    const PAGE: &str = "<html><body><p>hello</p></body></html>";
+    const NOT_UTF8: &[u8] = b"\xff\xfe</body>";
+
+    struct Panics;
+
+    impl RespMod for Panics {
+        fn name(&self) -> String {
+            String::from("panics")
         }
-        Err(e) => {
-            eprintln!("error converting bytes {:?}", e);
-            Ok(bytes)
+        fn process_str(&self, _resp: String) -> String {
+            panic!("oops")
+        }
+        fn guard(&self, _req_head: &RequestHead, _res_head: &ResponseHead) -> bool {
+            true
         }
     }
-}
 
-fn decode_gzip(bytes: Vec<u8>) -> io::Result<Vec<u8>> {
-    let mut d = GzDecoder::new(&bytes[..]);
-    let mut s = Vec::new();
-    d.read_to_end(&mut s).unwrap();
-    Ok(s)
-}
+    /// Leaves the body as-is, but can't stream
+    struct Buffered;
+
+    impl RespMod for Buffered {
+        fn name(&self) -> String {
+            String::from("buffered")
+        }
+        fn guard(&self, _req_head: &RequestHead, _res_head: &ResponseHead) -> bool {
+            true
+        }
+    }
 
-fn encode_gzip(input: Vec<u8>) -> io::Result<Vec<u8>> {
-    let mut e = GzEncoder::new(Vec::new(), Compression::default());
-    e.write_all(&input[..]).unwrap();
-    e.finish()
+    async fn static_file(mods: Vec<Box<dyn RespMod>>) -> (HeaderMap, Bytes) {
+        static_file_with(mods, Method::GET).await
+    }
+
+    async fn static_file_with(mods: Vec<Box<dyn RespMod>>, method: Method) -> (HeaderMap, Bytes) {
+        let fixtures = concat!(env!("CARGO_MANIFEST_DIR"), "/../fixtures/src");
+        let mut app = test::init_service(
+            App::new()
+                .data(RespModData { items: mods })
+                .wrap(RespModMiddleware)
+                .service(Files::new("/", fixtures)),
+        )
+        .await;
+        let req = TestRequest::with_uri("/index.html")
+            .method(method)
+            .header(ACCEPT, "text/html")
+            .to_request();
+        let res = test::call_service(&mut app, req).await;
+        let headers = res.headers().clone();
+        (headers, test::read_body(res).await)
+    }
+
+    async fn respond(mods: Vec<Box<dyn RespMod>>, res: fn() -> HttpResponse) -> (HeaderMap, Bytes) {
+        let mut app = test::init_service(
+            App::new()
+                .data(RespModData { items: mods })
+                .wrap(RespModMiddleware)
+                .default_service(web::to(move || ready(res()))),
+        )
+        .await;
+        let req = TestRequest::default()
+            .header(ACCEPT, "text/html")
+            .to_request();
+        let res = test::call_service(&mut app, req).await;
+        let headers = res.headers().clone();
+        (headers, test::read_body(res).await)
+    }
+
+    fn encoded(mut res: HttpResponse, coding: &'static str) -> HttpResponse {
+        res.headers_mut()
+            .insert(CONTENT_ENCODING, HeaderValue::from_static(coding));
+        res
+    }
+
+    fn html(body: impl Into<Body>) -> HttpResponse {
+        HttpResponse::Ok()
+            .header(CONTENT_TYPE, "text/html")
+            .body(body)
+    }
+
+    #[actix_rt::test]
+    async fn test_inner_error() {
+        let mut app = test::init_service(
+            App::new()
+                .data(RespModData {
+                    items: vec![Box::new(Script::default())],
+                })
+                .wrap_fn(|_req, _srv| {
+                    ready(Err::<ServiceResponse, _>(ErrorBadGateway(
+                        "upstream is down",
+                    )))
+                })
+                .wrap(RespModMiddleware),
+        )
+        .await;
+        let res = app.call(TestRequest::default().to_request()).await;
+        let err = res.err().expect("the error is passed on");
+        assert_eq!(
+            err.as_response_error().status_code(),
+            StatusCode::BAD_GATEWAY
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_corrupt_gzip() {
+        let (headers, body) = respond(vec![Box::new(Script::default())], || {
+            encoded(html("not gzip</body>"), "gzip")
+        })
+        .await;
+        assert_eq!(body, Bytes::from("not gzip</body>"));
+        assert_eq!(headers.get(CONTENT_ENCODING).expect("kept"), "gzip");
+    }
+
+    #[actix_rt::test]
+    async fn test_unsupported_encoding() {
+        let (_, body) = respond(vec![Box::new(Script::default())], || {
+            encoded(html("<body></body>"), "compress")
+        })
+        .await;
+        assert_eq!(body, Bytes::from("<body></body>"));
+    }
+
+    #[actix_rt::test]
+    async fn test_not_utf8() {
+        // Panics can't stream, so the body is buffered, and never reaches it
+        let (_, body) = respond(vec![Box::new(Script::default()), Box::new(Panics)], || {
+            html(NOT_UTF8)
+        })
+        .await;
+        assert_eq!(body, Bytes::from(NOT_UTF8));
+    }
+
+    #[actix_rt::test]
+    async fn test_panicking_mod() {
+        let (_, body) = respond(vec![Box::new(Script::default()), Box::new(Panics)], || {
+            html(PAGE)
+        })
+        .await;
+        assert_eq!(body, Bytes::from(PAGE));
+    }
+
+    #[actix_rt::test]
+    async fn test_modified() -> anyhow::Result<()> {
+        let (_, body) = respond(vec![Box::new(Script::default())], || {
+            let gzipped = encoding::encode(PAGE.as_bytes(), &[Coding::Gzip]).expect("gzip");
+            encoded(html(gzipped), "gzip")
+        })
+        .await;
+        let page = String::from_utf8(encoding::decode(&body, &[Coding::Gzip])?)?;
+        assert!(page.contains("/__bs3/client/index.js"));
+        Ok(())
+    }
+
+    #[actix_rt::test]
+    async fn test_head_request() {
+        let (headers, body) =
+            static_file_with(vec![Box::new(Script::default())], Method::HEAD).await;
+        assert!(body.is_empty());
+        assert!(headers.contains_key(ETAG));
+        assert!(headers.contains_key(LAST_MODIFIED));
+    }
+
+    #[actix_rt::test]
+    async fn test_nothing_to_modify() {
+        fn empty_stream() -> HttpResponse {
+            HttpResponse::Ok()
+                .header(CONTENT_TYPE, "text/html")
+                .streaming(futures::stream::empty::<Result<Bytes, Error>>())
+        }
+        // streamed, then buffered
+        for buffered in &[false, true] {
+            let mods = || -> Vec<Box<dyn RespMod>> {
+                if *buffered {
+                    vec![Box::new(Script::default()), Box::new(Buffered)]
+                } else {
+                    vec![Box::new(Script::default())]
+                }
+            };
+            let (_, body) = respond(mods(), || {
+                HttpResponse::NoContent()
+                    .header(CONTENT_TYPE, "text/html")
+                    .finish()
+            })
+            .await;
+            assert!(body.is_empty());
+            let (_, body) = respond(mods(), || {
+                HttpResponse::NotModified()
+                    .header(CONTENT_TYPE, "text/html")
+                    .finish()
+            })
+            .await;
+            assert!(body.is_empty());
+            let (_, body) = respond(mods(), || html("")).await;
+            assert!(body.is_empty());
+            let (_, body) = respond(mods(), empty_stream).await;
+            assert!(body.is_empty(), "buffered: {}", buffered);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_static_file_validators() -> anyhow::Result<()> {
+        let (headers, _) = static_file(vec![]).await;
+        assert!(headers.contains_key(ETAG));
+        assert!(headers.contains_key(LAST_MODIFIED));
+
+        // streamed, so there's no body to derive an ETag from up front
+        let (headers, body) = static_file(vec![Box::new(Script::default())]).await;
+        assert!(std::str::from_utf8(&body)?.contains("/__bs3/client/index.js"));
+        assert!(!headers.contains_key(ETAG));
+        assert!(!headers.contains_key(LAST_MODIFIED));
+
+        let (headers, body) =
+            static_file(vec![Box::new(Script::default()), Box::new(Buffered)]).await;
+        assert!(std::str::from_utf8(&body)?.contains("/__bs3/client/index.js"));
+        assert!(headers
+            .get(ETAG)
+            .expect("etag")
+            .to_str()?
+            .starts_with("W/\""));
+        assert!(!headers.contains_key(LAST_MODIFIED));
+
+        // the same body always gives the same ETag
+        let (again, _) = static_file(vec![Box::new(Script::default()), Box::new(Buffered)]).await;
+        assert_eq!(headers.get(ETAG), again.get(ETAG));
+        Ok(())
+    }
+
+    #[actix_rt::test]
+    async fn test_digests_removed() {
+        let (headers, _) = respond(
+            vec![Box::new(Script::default()), Box::new(Buffered)],
+            || {
+                let mut res = html(PAGE);
+                res.headers_mut()
+                    .insert(ETAG, HeaderValue::from_static(r#""upstream""#));
+                res.headers_mut().insert(
+                    HeaderName::from_static("content-md5"),
+                    HeaderValue::from_static("Q2hlY2sgSW50ZWdyaXR5IQ=="),
+                );
+                res.headers_mut().insert(
+                    HeaderName::from_static("digest"),
+                    HeaderValue::from_static(
+                        "sha-256=X48E9qOokqqrvdts8nOJRJN3OWDUoyWxBf7kbu9DBPE=",
+                    ),
+                );
+                res
+            },
+        )
+        .await;
+        assert_ne!(headers.get(ETAG).expect("etag"), r#""upstream""#);
+        assert!(!headers.contains_key("content-md5"));
+        assert!(!headers.contains_key("digest"));
+    }
+
+    #[actix_rt::test]
+    async fn test_csp_nonce() -> anyhow::Result<()> {
+        let (headers, body) = respond(vec![Box::new(Script::default())], || {
+            let mut res = html(PAGE);
+            res.headers_mut().insert(
+                CONTENT_SECURITY_POLICY,
+                HeaderValue::from_static("default-src 'self'"),
+            );
+            res
+        })
+        .await;
+        let csp = headers
+            .get(CONTENT_SECURITY_POLICY)
+            .expect("csp")
+            .to_str()?;
+        let nonce = csp
+            .split("'nonce-")
+            .nth(1)
+            .and_then(|rest| rest.split('\'').next())
+            .expect("a nonce was added");
+        assert!(std::str::from_utf8(&body)?.contains(&format!(r#"nonce="{}""#, nonce)));
+        Ok(())
+    }
 }
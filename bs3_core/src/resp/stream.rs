@@ -0,0 +1,395 @@
+use crate::resp::RespModError;
+use actix_web::dev::{Body, BodySize, MessageBody, ResponseBody};
+use actix_web::web::Bytes;
+use actix_web::Error;
+use futures_util::StreamExt;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+///
+/// A `RespMod` that can rewrite a response as it streams through,
+/// one chunk at a time, instead of waiting for the entire body.
+///
+/// A new one is created for each response, so it may hold state between chunks
+///
+pub trait StreamMod {
+    ///
+    /// Rewrite the next piece of text. Anything that could be the start of a match
+    /// may be held back, to be returned with a later chunk
+    ///
+    fn process_chunk(&mut self, chunk: String) -> String;
+    ///
+    /// The body has ended, return anything still held back
+    ///
+    fn finish(&mut self) -> String;
+    ///
+    /// The input held back so far, exactly as it was received. It's sent as-is
+    /// if a mod fails part-way through the body
+    ///
+    fn held_back(&self) -> &str;
+}
+
+///
+/// Replaces every occurrence of `from` with `to`, including occurrences that are
+/// split across chunks, eg: `</bo` + `dy>`
+///
+#[derive(Debug, Clone)]
+pub struct Replace {
+    from: String,
+    to: String,
+    /// Input that hasn't been returned yet, because it may be the start of `from`
+    pending: String,
+}
+
+impl Replace {
+    pub fn new(from: impl Into<String>, to: impl Into<String>) -> Self {
+        Self {
+            from: from.into(),
+            to: to.into(),
+            pending: String::new(),
+        }
+    }
+}
+
+///
+/// The length of the longest end of `text` that `pattern` starts with, ie: how much
+/// of `text` to hold back because the next chunk may complete a match
+///
+pub fn partial_match_len(text: &str, pattern: &str) -> usize {
+    (1..pattern.len())
+        .rev()
+        .filter(|len| pattern.is_char_boundary(*len))
+        .find(|len| text.ends_with(&pattern[..*len]))
+        .unwrap_or(0)
+}
+
+impl StreamMod for Replace {
+    fn process_chunk(&mut self, chunk: String) -> String {
+        if self.from.is_empty() {
+            return chunk;
+        }
+        self.pending.push_str(&chunk);
+        let mut output = String::with_capacity(self.pending.len());
+        // the replacement is never searched again, only the input
+        while let Some(index) = self.pending.find(&self.from) {
+            output.push_str(&self.pending[..index]);
+            output.push_str(&self.to);
+            self.pending.drain(..index + self.from.len());
+        }
+        let keep = partial_match_len(&self.pending, &self.from);
+        output.extend(self.pending.drain(..self.pending.len() - keep));
+        output
+    }
+    fn finish(&mut self) -> String {
+        std::mem::take(&mut self.pending)
+    }
+    fn held_back(&self) -> &str {
+        &self.pending
+    }
+}
+
+///
+/// Runs a response body through a chain of `StreamMod`s, the output of each
+/// one being the input of the next.
+///
+/// Chunks can end part-way through a multi-byte character, so incomplete
+/// characters are held until the next chunk. If the body turns out not to be
+/// UTF-8, or a mod panics, the rest of it is passed through untouched, starting
+/// with whatever the mods were holding back
+///
+pub struct StreamRewriter {
+    /// Each named after the `RespMod` it came from
+    mods: Vec<(String, Box<dyn StreamMod>)>,
+    /// Bytes of an incomplete character at the end of the last chunk
+    incomplete: Vec<u8>,
+    passthrough: bool,
+    /// Nothing has been read yet, an empty body is never modified
+    empty: bool,
+}
+
+impl StreamRewriter {
+    pub fn new(mods: Vec<(String, Box<dyn StreamMod>)>) -> Self {
+        Self {
+            mods,
+            incomplete: vec![],
+            passthrough: false,
+            empty: true,
+        }
+    }
+
+    pub fn chunk(&mut self, bytes: &[u8]) -> Bytes {
+        if self.passthrough {
+            return Bytes::copy_from_slice(bytes);
+        }
+        self.empty = self.empty && bytes.is_empty();
+        self.incomplete.extend_from_slice(bytes);
+        let valid = match std::str::from_utf8(&self.incomplete) {
+            Ok(text) => text.len(),
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(e) => {
+                log::debug!("streamed body is not utf8, passing it through {}", e);
+                self.passthrough = true;
+                return self.flush();
+            }
+        };
+        let bytes: Vec<u8> = self.incomplete.drain(..valid).collect();
+        let text = String::from_utf8(bytes).expect("validated above");
+        let held_back = self.held_back();
+        match self.run(text.clone(), false) {
+            Ok(output) => Bytes::from(output),
+            Err(e) => {
+                log::error!("passing the rest of the stream through unmodified: {}", e);
+                self.passthrough = true;
+                Bytes::from(held_back + &text)
+            }
+        }
+    }
+
+    /// The body has ended, flush everything held back
+    pub fn finish(&mut self) -> Bytes {
+        if self.passthrough || self.empty {
+            return Bytes::from(std::mem::take(&mut self.incomplete));
+        }
+        self.flush()
+    }
+
+    fn flush(&mut self) -> Bytes {
+        let held_back = self.held_back();
+        let text = self.run(String::new(), true).unwrap_or_else(|e| {
+            log::error!("could not flush a streamed response: {}", e);
+            held_back
+        });
+        let mut output = text.into_bytes();
+        output.append(&mut self.incomplete);
+        Bytes::from(output)
+    }
+
+    ///
+    /// Everything the mods are holding back, in body order. Each mod holds back
+    /// the end of its input, which comes after what later mods are holding
+    ///
+    fn held_back(&self) -> String {
+        self.mods
+            .iter()
+            .rev()
+            .map(|(_, item)| item.held_back())
+            .collect()
+    }
+
+    /// Run `text` through every mod, also flushing each one when `finish` is set
+    fn run(&mut self, text: String, finish: bool) -> Result<String, RespModError> {
+        let mut output = text;
+        for (name, item) in self.mods.iter_mut() {
+            let input = output;
+            output = panic::catch_unwind(AssertUnwindSafe(|| {
+                let mut output = item.process_chunk(input);
+                if finish {
+                    output.push_str(&item.finish());
+                }
+                output
+            }))
+            .map_err(|_| RespModError::Panicked(name.clone()))?;
+        }
+        Ok(output)
+    }
+}
+
+///
+/// A response body, rewritten chunk by chunk as it's sent
+///
+pub struct RewriteBody {
+    body: ResponseBody<Body>,
+    rewriter: StreamRewriter,
+    done: bool,
+}
+
+impl RewriteBody {
+    pub fn new(body: ResponseBody<Body>, mods: Vec<(String, Box<dyn StreamMod>)>) -> Self {
+        Self {
+            body,
+            rewriter: StreamRewriter::new(mods),
+            done: false,
+        }
+    }
+}
+
+impl MessageBody for RewriteBody {
+    fn size(&self) -> BodySize {
+        BodySize::Stream
+    }
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Bytes, Error>>> {
+        let this = self.get_mut();
+        loop {
+            if this.done {
+                return Poll::Ready(None);
+            }
+            match this.body.poll_next_unpin(cx) {
+                Poll::Ready(Some(Ok(chunk))) => {
+                    let output = this.rewriter.chunk(&chunk);
+                    // everything was held back, wait for more
+                    if output.is_empty() {
+                        continue;
+                    }
+                    return Poll::Ready(Some(Ok(output)));
+                }
+                Poll::Ready(Some(Err(e))) => {
+                    this.done = true;
+                    return Poll::Ready(Some(Err(e)));
+                }
+                Poll::Ready(None) => {
+                    this.done = true;
+                    let output = this.rewriter.finish();
+                    if !output.is_empty() {
+                        return Poll::Ready(Some(Ok(output)));
+                    }
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn named(mods: Vec<Box<dyn StreamMod>>) -> Vec<(String, Box<dyn StreamMod>)> {
+        mods.into_iter()
+            .enumerate()
+            .map(|(index, item)| (format!("mod {}", index), item))
+            .collect()
+    }
+
+    fn rewrite(mods: Vec<Box<dyn StreamMod>>, chunks: &[&[u8]]) -> String {
+        let mut rewriter = StreamRewriter::new(named(mods));
+        let mut output: Vec<u8> = vec![];
+        for chunk in chunks {
+            output.extend_from_slice(&rewriter.chunk(chunk));
+        }
+        output.extend_from_slice(&rewriter.finish());
+        String::from_utf8(output).expect("utf8")
+    }
+
+    #[test]
+    fn test_replace_across_chunks() {
+        let mut replace = Replace::new("</body>", "<script></script></body>");
+        assert_eq!(
+            replace.process_chunk(String::from("<p>hi</p></bo")),
+            "<p>hi</p>"
+        );
+        assert_eq!(
+            replace.process_chunk(String::from("dy></html>")),
+            "<script></script></body></html>"
+        );
+        assert_eq!(replace.finish(), "");
+    }
+
+    #[test]
+    fn test_partial_match_that_isnt() {
+        let mut replace = Replace::new("</body>", "X");
+        assert_eq!(replace.process_chunk(String::from("a </b")), "a ");
+        assert_eq!(replace.process_chunk(String::from(">bold")), "</b>bold");
+        assert_eq!(replace.process_chunk(String::from(" <")), " ");
+        assert_eq!(replace.finish(), "<");
+    }
+
+    #[test]
+    fn test_chained_mods() {
+        let output = rewrite(
+            vec![
+                Box::new(Replace::new("</body>", "<script></script></body>")),
+                Box::new(Replace::new("script", "SCRIPT")),
+            ],
+            &[
+                "<body>scr".as_bytes(),
+                "ipt</bo".as_bytes(),
+                "dy>".as_bytes(),
+            ],
+        );
+        assert_eq!(output, "<body>SCRIPT<SCRIPT></SCRIPT></body>");
+    }
+
+    #[test]
+    fn test_split_characters() {
+        let text = "<p>héllo wörld</p></body>".as_bytes();
+        let chunks: Vec<&[u8]> = text.chunks(3).collect();
+        let output = rewrite(vec![Box::new(Replace::new("</body>", "!</body>"))], &chunks);
+        assert_eq!(output, "<p>héllo wörld</p>!</body>");
+    }
+
+    #[test]
+    fn test_not_utf8() {
+        let mut rewriter = StreamRewriter::new(named(vec![Box::new(Replace::new("a", "b"))]));
+        assert_eq!(rewriter.chunk(b"aa"), Bytes::from("bb"));
+        assert_eq!(rewriter.chunk(&[0xff, b'a']), Bytes::from(vec![0xff, b'a']));
+        assert_eq!(rewriter.chunk(b"a"), Bytes::from("a"));
+    }
+
+    struct Panics;
+
+    impl StreamMod for Panics {
+        fn process_chunk(&mut self, chunk: String) -> String {
+            if chunk.contains("boom") {
+                panic!("boom");
+            }
+            chunk
+        }
+        fn finish(&mut self) -> String {
+            String::new()
+        }
+        fn held_back(&self) -> &str {
+            ""
+        }
+    }
+
+    struct PanicsOnFinish;
+
+    impl StreamMod for PanicsOnFinish {
+        fn process_chunk(&mut self, chunk: String) -> String {
+            chunk
+        }
+        fn finish(&mut self) -> String {
+            panic!("boom");
+        }
+        fn held_back(&self) -> &str {
+            ""
+        }
+    }
+
+    #[test]
+    fn test_panicking_mod() {
+        let mut rewriter = StreamRewriter::new(named(vec![
+            Box::new(Replace::new("a", "b")),
+            Box::new(Panics),
+        ]));
+        assert_eq!(rewriter.chunk(b"aa"), Bytes::from("bb"));
+        assert_eq!(rewriter.chunk(b"a boom"), Bytes::from("a boom"));
+        assert_eq!(rewriter.chunk(b"a"), Bytes::from("a"));
+        assert_eq!(rewriter.finish(), Bytes::new());
+    }
+
+    #[test]
+    fn test_panic_with_partial_match_held_back() {
+        let output = rewrite(
+            vec![Box::new(Replace::new("</body>", "X")), Box::new(Panics)],
+            &[
+                "<p>a</p></bo".as_bytes(),
+                "dy> boom".as_bytes(),
+                "!".as_bytes(),
+            ],
+        );
+        assert_eq!(output, "<p>a</p></body> boom!");
+
+        let output = rewrite(
+            vec![
+                Box::new(Replace::new("</body>", "X")),
+                Box::new(Replace::new("</p>", "Y")),
+                Box::new(PanicsOnFinish),
+            ],
+            &["<p>a</p".as_bytes(), "></bo".as_bytes()],
+        );
+        assert_eq!(output, "<p>aY</bo");
+    }
+}
@@ -0,0 +1,262 @@
+use crate::proxy::is_proxied;
+use crate::resp::stream::{Replace, StreamMod};
+use crate::resp::RespMod;
+use actix_web::dev::{RequestHead, ResponseHead};
+use actix_web::http::header::CONTENT_TYPE;
+use globset::{Glob, GlobMatcher};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+///
+/// A user-defined find & replace on response bodies, from `rewriteRules` in config
+///
+/// eg: swap a production analytics snippet in proxied pages
+///
+/// ```json
+/// {
+///     "match": { "regex": "https://cdn(\\d)\\.example\\.com" },
+///     "replace": "http://localhost:8090/cdn$1",
+///     "path": "/blog/**",
+///     "contentType": "text/html",
+///     "source": "proxy"
+/// }
+/// ```
+///
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
+pub struct RewriteRule {
+    #[serde(rename = "match")]
+    pub matcher: RewriteMatch,
+    /// Regex rules can refer to capture groups, eg: `$1` or `${name}`
+    pub replace: String,
+    /// Only rewrite responses for request paths matching this glob
+    #[serde(default)]
+    pub path: Option<String>,
+    /// Only rewrite responses whose content-type contains this
+    #[serde(
+        rename = "contentType",
+        default = "crate::rewrite_rules::default_content_type"
+    )]
+    pub content_type: String,
+    #[serde(default)]
+    pub source: RewriteSource,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum RewriteMatch {
+    Literal(String),
+    Regex { regex: String },
+}
+
+///
+/// Where the response came from
+///
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RewriteSource {
+    Any,
+    /// Responses from a `--proxy` target
+    Proxy,
+    /// Everything else, eg: files served from disk
+    Static,
+}
+
+impl Default for RewriteSource {
+    fn default() -> Self {
+        RewriteSource::Any
+    }
+}
+
+pub fn default_content_type() -> String {
+    String::from("text/html")
+}
+
+#[derive(Debug, Clone)]
+enum Matcher {
+    Literal(String),
+    Regex(Regex),
+}
+
+///
+/// The compiled form of a `RewriteRule`
+///
+#[derive(Debug, Clone)]
+pub struct RewriteMod {
+    matcher: Matcher,
+    replace: String,
+    path: Option<GlobMatcher>,
+    content_type: String,
+    source: RewriteSource,
+    name: String,
+}
+
+impl RewriteMod {
+    pub fn new(rule: &RewriteRule) -> Result<Self, RewriteRuleError> {
+        let (matcher, name) = match &rule.matcher {
+            RewriteMatch::Literal(literal) => (
+                Matcher::Literal(literal.to_owned()),
+                format!("rewrite rule `{}`", literal),
+            ),
+            RewriteMatch::Regex { regex } => (
+                Matcher::Regex(
+                    Regex::new(regex).map_err(|e| RewriteRuleError::InvalidRegex(e.to_string()))?,
+                ),
+                format!("rewrite rule /{}/", regex),
+            ),
+        };
+        let path = match &rule.path {
+            Some(glob) => Some(
+                Glob::new(glob)
+                    .map_err(|e| RewriteRuleError::InvalidGlob(e.to_string()))?
+                    .compile_matcher(),
+            ),
+            None => None,
+        };
+        Ok(Self {
+            matcher,
+            replace: rule.replace.to_owned(),
+            path,
+            content_type: rule.content_type.to_owned(),
+            source: rule.source,
+            name,
+        })
+    }
+    ///
+    /// Compile every rule, in order. Invalid rules are logged & skipped
+    ///
+    pub fn from_rules(rules: &[RewriteRule]) -> Vec<RewriteMod> {
+        rules
+            .iter()
+            .filter_map(|rule| match RewriteMod::new(rule) {
+                Ok(item) => Some(item),
+                Err(e) => {
+                    log::error!("skipping rewrite rule {:?}: {}", rule.matcher, e);
+                    None
+                }
+            })
+            .collect()
+    }
+}
+
+impl RespMod for RewriteMod {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+    fn process_str(&self, str: String) -> String {
+        match &self.matcher {
+            Matcher::Literal(literal) if literal.is_empty() => str,
+            Matcher::Literal(literal) => str.replace(literal.as_str(), &self.replace),
+            Matcher::Regex(regex) => regex.replace_all(&str, self.replace.as_str()).into_owned(),
+        }
+    }
+    /// Only literal matches can span chunks, a regex needs the entire body
+    fn stream(&self) -> Option<Box<dyn StreamMod>> {
+        match &self.matcher {
+            Matcher::Literal(literal) => Some(Box::new(Replace::new(literal, &self.replace))),
+            Matcher::Regex(_) => None,
+        }
+    }
+    fn guard(&self, req_head: &RequestHead, res_head: &ResponseHead) -> bool {
+        let content_type = res_head
+            .headers
+            .get(CONTENT_TYPE)
+            .and_then(|hv| hv.to_str().ok())
+            .filter(|str| str.contains(&self.content_type))
+            .is_some();
+        let path = self
+            .path
+            .as_ref()
+            .map(|glob| glob.is_match(req_head.uri.path()))
+            .unwrap_or(true);
+        let source = match self.source {
+            RewriteSource::Any => true,
+            RewriteSource::Proxy => is_proxied(res_head),
+            RewriteSource::Static => !is_proxied(res_head),
+        };
+        content_type && path && source
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RewriteRuleError {
+    #[error("invalid regex: {0}")]
+    InvalidRegex(String),
+    #[error("invalid path glob: {0}")]
+    InvalidGlob(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::Proxied;
+    use actix_web::http::{HeaderValue, StatusCode};
+    use actix_web::test::TestRequest;
+
+    fn rule(json: &str) -> RewriteMod {
+        let rule: RewriteRule = serde_json::from_str(json).expect("valid rule");
+        RewriteMod::new(&rule).expect("compiles")
+    }
+
+    fn html_head() -> ResponseHead {
+        let mut head = ResponseHead::new(StatusCode::OK);
+        head.headers
+            .insert(CONTENT_TYPE, HeaderValue::from_static("text/html"));
+        head
+    }
+
+    #[test]
+    fn test_replace() {
+        let literal = rule(r#"{"match": "UA-1234", "replace": "UA-DEV"}"#);
+        assert_eq!(
+            literal.process_str(String::from("a UA-1234 b UA-1234")),
+            "a UA-DEV b UA-DEV"
+        );
+        let regex = rule(
+            r#"{"match": {"regex": "https://cdn(\\d)\\.example\\.com"}, "replace": "/cdn$1"}"#,
+        );
+        assert_eq!(
+            regex.process_str(String::from(
+                r#"<img src="https://cdn2.example.com/a.png">"#
+            )),
+            r#"<img src="/cdn2/a.png">"#
+        );
+        assert!(literal.stream().is_some());
+        assert!(regex.stream().is_none());
+    }
+
+    #[test]
+    fn test_guards() {
+        let req = TestRequest::with_uri("/blog/post-1").to_http_request();
+        let head = html_head();
+
+        let any = rule(r#"{"match": "a", "replace": "b"}"#);
+        assert!(any.guard(req.head(), &head));
+
+        let other_path = rule(r#"{"match": "a", "replace": "b", "path": "/shop/**"}"#);
+        assert!(!other_path.guard(req.head(), &head));
+
+        let css = rule(r#"{"match": "a", "replace": "b", "contentType": "text/css"}"#);
+        assert!(!css.guard(req.head(), &head));
+
+        let proxied = rule(r#"{"match": "a", "replace": "b", "source": "proxy"}"#);
+        let fs = rule(r#"{"match": "a", "replace": "b", "source": "static"}"#);
+        assert!(!proxied.guard(req.head(), &head));
+        assert!(fs.guard(req.head(), &head));
+        head.extensions_mut().insert(Proxied);
+        assert!(proxied.guard(req.head(), &head));
+        assert!(!fs.guard(req.head(), &head));
+    }
+
+    #[test]
+    fn test_invalid_rules_are_skipped() -> anyhow::Result<()> {
+        let rules: Vec<RewriteRule> = serde_json::from_str(
+            r#"[
+                {"match": {"regex": "(unclosed"}, "replace": ""},
+                {"match": "a", "replace": "b", "path": "/{unclosed"},
+                {"match": "a", "replace": "b"}
+            ]"#,
+        )?;
+        assert_eq!(RewriteMod::from_rules(&rules).len(), 1);
+        Ok(())
+    }
+}
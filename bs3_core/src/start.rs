@@ -13,20 +13,28 @@ use tokio::sync::oneshot;
 
 use crate::server::{Server, ServerIncoming};
 use crate::{
+    api,
     browser_sync::BrowserSync,
     bs_error::BsError,
     client::css::Css,
+    client::hmr::HotContext,
     client::script::Script,
     fs::FsWatcher,
     fs::RegisterFs,
+    header_rules::{HeaderRules, HeaderRulesMiddleware},
     proxy::proxy_resp_mod::ProxyResp,
     proxy::service::ProxyService,
     proxy::Proxy,
+    reload_rules::ReloadRules,
     resp,
     resp::RespModData,
+    rewrite_rules::RewriteMod,
     routes::not_found::NotFound,
     serve_static::{ServeStatic, ServeStaticConfig},
-    ws::server::WsServer,
+    ws::livereload::{livereload_route, LiveReloadServer},
+    ws::relay,
+    ws::server::{RegisterLiveReload, WsServer},
+    ws::sse_session::{msg_route, sse_route, SseSessions},
     ws::ws_session::ws_route,
 };
 use actix_rt::time::delay_for;
@@ -48,7 +56,7 @@ pub async fn main(
 ) -> anyhow::Result<Final> {
     let (stop_msg_sender, stop_msg_receiver) = oneshot::channel();
 
-    let ws_server = WsServer::default().start();
+    let ws_server = WsServer::from_config(&browser_sync.config).start();
     let fs_server = FsWatcher::default().start();
     let served_files = Served::default().start();
 
@@ -57,14 +65,69 @@ pub async fn main(
         addr: fs_server.clone().recipient(),
     });
 
+    // let the WS server know which page each file was served for
+    served_files.do_send(Register {
+        addr: ws_server.clone().recipient(),
+    });
+
     fs_server.do_send(RegisterFs {
         addr: ws_server.clone().recipient(),
     });
 
+    if !browser_sync.config.relay.is_empty() && browser_sync.config.relay_token.is_none() {
+        log::warn!("--relay without --relay-token, other instances will not accept it as a peer");
+    }
+    for target in &browser_sync.config.relay {
+        relay::start(
+            target.clone(),
+            browser_sync.config.relay_token.clone(),
+            ws_server.clone(),
+        );
+    }
+
+    let livereload_port = browser_sync.config.livereload_port;
+    let livereload = if browser_sync.config.livereload || livereload_port.is_some() {
+        let rules = ReloadRules::new(&browser_sync.config.reload_rules);
+        let livereload = LiveReloadServer::new(rules).start();
+        fs_server.do_send(RegisterFs {
+            addr: livereload.clone().recipient(),
+        });
+        ws_server.do_send(RegisterLiveReload {
+            addr: livereload.clone().recipient(),
+        });
+        Some(livereload)
+    } else {
+        None
+    };
+
+    let rewrite_mods = RewriteMod::from_rules(&browser_sync.config.rewrite_rules);
+    let header_rules = web::Data::new(HeaderRules::new(&browser_sync.config.headers));
+
     let port = browser_sync.local_url.0.port();
     let bind_address = browser_sync.bind_address();
     dbg!(&bind_address);
 
+    // LiveReload extensions only ever connect to their own port
+    if let (Some(livereload), Some(lr_port)) = (livereload.clone(), livereload_port) {
+        let host = browser_sync.local_url.0.host_str().unwrap_or("0.0.0.0");
+        let lr_address = format!("{}:{}", host, lr_port);
+        let lr_server = HttpServer::new(move || {
+            App::new()
+                .data(livereload.clone())
+                .service(web::resource("/livereload").to(livereload_route))
+        });
+        match lr_server.workers(1).bind(&lr_address) {
+            Ok(lr_server) => {
+                actix_rt::spawn(async move {
+                    if let Err(e) = lr_server.run().await {
+                        log::error!("livereload server stopped: {}", e);
+                    }
+                });
+            }
+            Err(e) => eprintln!("could not start livereload on {}: {}", lr_address, e),
+        }
+    }
+
     let s = Server {
         ws_server: ws_server.clone(),
         fs_server: fs_server.clone(),
@@ -75,6 +138,9 @@ pub async fn main(
 
     let addr = s.start();
 
+    // shared across workers, so that a message can reach its session
+    let sse_sessions = web::Data::new(SseSessions::default());
+
     let server = HttpServer::new(move || {
         let served_addr = Arc::new(ServedAddr(served_files.clone()));
         let ss_config = browser_sync.config.serve_static_config();
@@ -85,9 +151,23 @@ pub async fn main(
         let local_url = browser_sync.local_url.0.clone();
 
         let mut mods = RespModData {
-            items: vec![Box::new(Script), Box::new(Css)],
+            items: vec![
+                Box::new(
+                    Script::new(&browser_sync.config.script)
+                        .with_eval(browser_sync.config.hmr || browser_sync.config.allow_eval),
+                ),
+                Box::new(Css),
+            ],
         };
 
+        if browser_sync.config.hmr {
+            mods.items.push(Box::new(HotContext));
+        }
+
+        for rewrite in rewrite_mods.iter().cloned() {
+            mods.items.push(Box::new(rewrite));
+        }
+
         // if the proxy is configured & has no path - assume the entire website is being proxied
         if !proxy_config_arc.is_empty() {
             let first_without_paths = proxy_config_arc.iter().find(|pt| pt.paths.is_empty());
@@ -106,13 +186,26 @@ pub async fn main(
             .data(mods)
             .data(served_addr)
             .data(ss_config_arc.clone())
+            .app_data(sse_sessions.clone())
+            .app_data(header_rules.clone())
+            // inside RespModMiddleware, so that headers it sets are seen by each RespMod
+            .wrap(HeaderRulesMiddleware)
             .wrap(resp::RespModMiddleware)
             .service(web::resource("/__bs3/ws/").to(ws_route))
+            .service(web::resource("/__bs3/sse").to(sse_route))
+            .service(web::resource("/__bs3/msg").route(web::post().to(msg_route)))
+            .service(api::scope())
             .service(Files::new(
                 "/__bs3/client",
                 "/Users/shaneosbourne/WebstormProjects/bs3/bs3_client/dist",
             ));
 
+        if let Some(livereload) = livereload.clone() {
+            app = app
+                .data(livereload)
+                .service(web::resource("/livereload").to(livereload_route));
+        }
+
         let index = browser_sync
             .config
             .index
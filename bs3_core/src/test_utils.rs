@@ -1,6 +1,9 @@
 use crate::browser_sync::BrowserSync;
 use crate::config::get_available_port;
 use crate::start::main;
+use crate::ws::client::ClientMsg;
+use crate::ws::server::{Connect, WsServer};
+use actix::{Actor, Addr, Handler};
 use actix_web::client::{Client, ClientResponse};
 use actix_web::dev::{Decompress, Payload};
 use actix_web::error::PayloadError;
@@ -9,6 +12,7 @@ use actix_web::web::Bytes;
 use anyhow::Context;
 use std::future::Future;
 use std::pin::Pin;
+use std::sync::{Arc, Mutex};
 
 type TestError = Option<String>;
 type TestOutput = Result<TestError, anyhow::Error>;
@@ -129,3 +133,37 @@ fn runner(
     });
     Ok(())
 }
+
+/// Stands in for a session, keeping every message it's sent
+#[derive(Default, Clone)]
+pub struct Collector(Arc<Mutex<Vec<ClientMsg>>>);
+
+impl Actor for Collector {
+    type Context = actix::Context<Self>;
+}
+
+impl Handler<ClientMsg> for Collector {
+    type Result = ();
+
+    fn handle(&mut self, msg: ClientMsg, _: &mut actix::Context<Self>) {
+        self.0.lock().expect("lock").push(msg);
+    }
+}
+
+impl Collector {
+    /// Connect a new collector to `srv`, as if it were a browser
+    pub async fn connect(srv: &Addr<WsServer>) -> (usize, Collector) {
+        let collector = Collector::default();
+        let addr = collector.clone().start();
+        let id = srv
+            .send(Connect {
+                addr: addr.recipient(),
+            })
+            .await
+            .expect("connect");
+        (id, collector)
+    }
+    pub fn received(&self) -> Vec<ClientMsg> {
+        self.0.lock().expect("lock").clone()
+    }
+}
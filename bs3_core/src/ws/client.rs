@@ -4,6 +4,12 @@ use wasm_bindgen::prelude::*;
 use std::path::PathBuf;
 use typescript_definitions::TypescriptDefinition;
 
+///
+/// Bump this whenever `ClientMsg` changes in a way that older client bundles
+/// can't handle, so that they're told to hard-reload rather than failing silently
+///
+pub const PROTOCOL_VERSION: u32 = 1;
+
 #[derive(
     Default,
     Clone,
@@ -26,13 +32,93 @@ impl actix::Message for ServedFile {
     type Result = ();
 }
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, TypescriptDefinition)]
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize, TypescriptDefinition)]
 #[serde(tag = "kind", content = "payload")]
 pub enum ClientMsg {
     Connect,
     Disconnect,
+    /// Sent by the browser as soon as it connects. Bundles from before versioning
+    /// was added don't send `version`, so it defaults to 0
+    Hello {
+        url: String,
+        user_agent: String,
+        #[serde(default)]
+        version: u32,
+        #[serde(default)]
+        features: Vec<String>,
+        /// Only sent by other bs3 instances, see `--relay-token`
+        #[serde(default)]
+        relay_token: Option<String>,
+    },
+    /// The server's reply to `Hello`
+    Welcome(WelcomeMsg),
+    /// The client bundle is outdated, reload it bypassing the cache
+    HardReload {
+        reason: String,
+    },
+    /// Sent by the browser after `Hello`, and whenever it's resized
+    Viewport(ViewportMsg),
     Scroll(ScrollMsg),
-    FsNotify(FsNotify),
+    Click(ClickMsg),
+    /// `input` & `change` events on text fields, textareas & selects
+    Input(InputMsg),
+    /// checkboxes & radios
+    Toggle(ToggleMsg),
+    Submit(SubmitMsg),
+    /// Send browsers to another page, on whatever host each one is already using
+    Navigate(NavigateMsg),
+    /// `console.*` calls, uncaught errors & unhandled rejections from the browser
+    Log {
+        level: LogLevel,
+        args: Vec<String>,
+        stack: Option<String>,
+        url: Option<String>,
+    },
+    /// Swap a stylesheet in place, without reloading the page
+    InjectCss {
+        web_path: String,
+    },
+    /// Swap an image in place, without reloading the page
+    ReplaceImage {
+        web_path: String,
+    },
+    /// Full page reload
+    Reload {
+        reason: String,
+    },
+    /// A short-lived toast message, `timeout` is in milliseconds
+    Notify {
+        message: String,
+        timeout: Option<u32>,
+    },
+    /// A full-screen overlay that stays until `ClearOverlay`, or the next reload
+    ErrorOverlay(ErrorOverlayMsg),
+    ClearOverlay,
+    /// Run `code` in the browser, only ever sent when `--allow-eval` is given
+    Eval {
+        id: u64,
+        code: String,
+    },
+    /// The browser's reply to `Eval`, exactly one of `value` or `error` is set
+    EvalResult {
+        id: u64,
+        value: Option<String>,
+        error: Option<String>,
+    },
+    /// Only exchanged between federated bs3 instances, never with browsers
+    Relay(RelayMsg),
+    /// Re-import changed ES modules in place, only sent when `--hmr` is given
+    /// and every importer of each module accepts the update
+    HmrUpdate {
+        modules: Vec<String>,
+        timestamp: u64,
+    },
+    /// Sent by the browser for each `import.meta.hot.accept()` call. An empty `deps`
+    /// means `module` accepts updates to itself
+    HmrAccept {
+        module: String,
+        deps: Vec<String>,
+    },
 }
 
 #[cfg(not(target_arch = "wasm32"))]
@@ -56,12 +142,119 @@ impl FsNotify {
     }
 }
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, TypescriptDefinition)]
+#[derive(
+    Debug, Copy, Clone, Eq, PartialEq, serde::Serialize, serde::Deserialize, TypescriptDefinition,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Debug,
+    Log,
+    Info,
+    Warn,
+    Error,
+}
+
+impl std::str::FromStr for LogLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "debug" => Ok(LogLevel::Debug),
+            "log" => Ok(LogLevel::Log),
+            "info" => Ok(LogLevel::Info),
+            "warn" => Ok(LogLevel::Warn),
+            "error" => Ok(LogLevel::Error),
+            _ => Err(format!(
+                "invalid log level `{}`, expected one of: debug, log, info, warn, error",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize, TypescriptDefinition)]
+pub struct WelcomeMsg {
+    pub version: u32,
+    /// eg: `ghost.scroll`, `eval`
+    pub features: Vec<String>,
+    /// In the same `action:glob` form as `--reload-rule`, in the order they're checked
+    pub reload_rules: Vec<String>,
+}
+
+/// A message forwarded between bs3 instances started with `--relay`
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize, TypescriptDefinition)]
+pub struct RelayMsg {
+    /// Random, so that each instance forwards a message at most once
+    pub id: u64,
+    /// The room a ghost-mode message belongs to, `None` for every page
+    pub room: Option<String>,
+    pub msg: Box<ClientMsg>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize, TypescriptDefinition)]
+pub struct ViewportMsg {
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Scroll positions are proportional to the scrollable distance (0.0 - 1.0),
+/// so that devices with different viewport sizes end up in the same place
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize, TypescriptDefinition)]
 pub struct ScrollMsg {
     pub x: f64,
     pub y: f64,
 }
 
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize, TypescriptDefinition)]
+pub struct ClickMsg {
+    pub selector: String,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize, TypescriptDefinition)]
+pub struct InputMsg {
+    pub selector: String,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize, TypescriptDefinition)]
+pub struct ToggleMsg {
+    pub selector: String,
+    pub checked: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize, TypescriptDefinition)]
+pub struct SubmitMsg {
+    pub selector: String,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize, TypescriptDefinition)]
+pub struct NavigateMsg {
+    pub path: String,
+    pub query: Option<String>,
+    pub hash: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize, TypescriptDefinition)]
+pub struct ErrorOverlayMsg {
+    pub title: String,
+    pub body: String,
+    pub file: Option<String>,
+    pub line: Option<u32>,
+}
+
+#[test]
+fn test_hello_without_version() {
+    let js = serde_json::json!({
+        "kind": "Hello",
+        "payload": {
+            "url": "http://localhost:8090/",
+            "user_agent": "curl"
+        }
+    });
+    let msg: ClientMsg = serde_json::from_value(js).expect("test");
+    assert!(matches!(msg, ClientMsg::Hello { version: 0, .. }));
+}
+
 #[test]
 fn test_client_msg() {
     let js = serde_json::json!({
@@ -0,0 +1,69 @@
+use crate::ws::client::LogLevel;
+
+///
+/// Format a browser log for the terminal, eg:
+///
+/// `[bs3] [Safari 15 (iOS)] error: Uncaught TypeError: x is undefined`
+///
+pub fn format_log(
+    device: &str,
+    level: LogLevel,
+    args: &[String],
+    stack: Option<&str>,
+    url: Option<&str>,
+) -> String {
+    let mut output = format!(
+        "[bs3] [{}] {}",
+        device,
+        colour(level, &format!("{}: {}", label(level), args.join(" ")))
+    );
+    if let Some(url) = url {
+        output.push_str(&format!("\n    at {}", url));
+    }
+    if let Some(stack) = stack {
+        for line in stack.lines() {
+            output.push_str(&format!("\n    {}", colour(LogLevel::Debug, line.trim())));
+        }
+    }
+    output
+}
+
+fn label(level: LogLevel) -> &'static str {
+    match level {
+        LogLevel::Debug => "debug",
+        LogLevel::Log => "log",
+        LogLevel::Info => "info",
+        LogLevel::Warn => "warn",
+        LogLevel::Error => "error",
+    }
+}
+
+fn colour(level: LogLevel, text: &str) -> String {
+    let code = match level {
+        LogLevel::Debug => "90",
+        LogLevel::Log => "0",
+        LogLevel::Info => "36",
+        LogLevel::Warn => "33",
+        LogLevel::Error => "31",
+    };
+    format!("\x1b[{}m{}\x1b[0m", code, text)
+}
+
+#[test]
+fn test_format_log() {
+    let args = vec![String::from("Uncaught"), String::from("oops")];
+    let output = format_log(
+        "Chrome 96 (Android)",
+        LogLevel::Error,
+        &args,
+        Some("Error: oops\n  at main.js:1:1"),
+        Some("http://localhost:8090/main.js:1:1"),
+    );
+    assert_eq!(
+        output,
+        "[bs3] [Chrome 96 (Android)] \x1b[31merror: Uncaught oops\x1b[0m\
+         \n    at http://localhost:8090/main.js:1:1\
+         \n    \x1b[90mError: oops\x1b[0m\
+         \n    \x1b[90mat main.js:1:1\x1b[0m"
+    );
+}
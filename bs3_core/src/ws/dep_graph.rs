@@ -0,0 +1,163 @@
+use crate::ws::client::ServedFile;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+///
+/// Page URL -> the assets that were served with that page as the referer
+///
+/// This allows a file change to be sent only to the browsers that are
+/// currently showing a page that depends on it
+///
+#[derive(Debug, Default)]
+pub struct DepGraph {
+    pages: HashMap<String, HashSet<String>>,
+}
+
+impl DepGraph {
+    ///
+    /// Only subresources are recorded. A page reached by following a link has the
+    /// previous page as its referer, but it isn't part of that page
+    ///
+    pub fn add(&mut self, served: &ServedFile) {
+        if let Some(page) = served.referer.as_deref().and_then(page_path) {
+            let asset = web_path(&served.web_path);
+            if asset != page && !is_document(&asset) && !self.pages.contains_key(&asset) {
+                self.pages
+                    .entry(page)
+                    .or_insert_with(HashSet::new)
+                    .insert(asset);
+            }
+        }
+    }
+    ///
+    /// The pages affected by a change to `changed`. That's every page that referenced it,
+    /// plus the file itself if it was ever seen as a page.
+    ///
+    /// `None` means nothing is known about this file, so every page should be considered
+    ///
+    pub fn affected_pages(&self, changed: &Path) -> Option<HashSet<String>> {
+        let changed = web_path(changed);
+        let mut affected: HashSet<String> = self
+            .pages
+            .iter()
+            .filter(|(_, assets)| assets.contains(&changed))
+            .map(|(page, _)| page.to_owned())
+            .collect();
+        if self.pages.contains_key(&changed) {
+            affected.insert(changed);
+        }
+        if affected.is_empty() {
+            None
+        } else {
+            Some(affected)
+        }
+    }
+}
+
+///
+/// Reduce a full URL such as a `referer` header, to the path only
+///
+/// eg: `http://localhost:8090/about.html?a=b` -> `/about.html`
+///
+pub fn page_path(url: &str) -> Option<String> {
+    url::Url::parse(url).ok().map(|url| url.path().to_string())
+}
+
+///
+/// Whether `web_path` is a page rather than a subresource, ie: html or a directory index
+///
+fn is_document(web_path: &str) -> bool {
+    if web_path.ends_with('/') {
+        return true;
+    }
+    match Path::new(web_path).extension().and_then(|ext| ext.to_str()) {
+        Some(ext) => ext.eq_ignore_ascii_case("html") || ext.eq_ignore_ascii_case("htm"),
+        None => false,
+    }
+}
+
+pub fn web_path(path: &Path) -> String {
+    let as_str = path.to_string_lossy();
+    if as_str.starts_with('/') {
+        as_str.to_string()
+    } else {
+        format!("/{}", as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn served(web_path: &str, referer: Option<&str>) -> ServedFile {
+        ServedFile {
+            path: PathBuf::from(format!("/www{}", web_path)),
+            web_path: PathBuf::from(web_path),
+            referer: referer.map(String::from),
+        }
+    }
+
+    #[test]
+    fn test_affected_pages() {
+        let mut graph = DepGraph::default();
+        graph.add(&served("/", None));
+        graph.add(&served("/style.css", Some("http://localhost:8090/")));
+        graph.add(&served(
+            "/style.css",
+            Some("http://localhost:8090/about.html"),
+        ));
+        graph.add(&served(
+            "/about.js",
+            Some("http://localhost:8090/about.html?a=b"),
+        ));
+
+        let css = graph
+            .affected_pages(&PathBuf::from("/style.css"))
+            .expect("known");
+        assert_eq!(css.len(), 2);
+
+        let js = graph
+            .affected_pages(&PathBuf::from("about.js"))
+            .expect("known");
+        assert_eq!(js, vec![String::from("/about.html")].into_iter().collect());
+
+        let page = graph
+            .affected_pages(&PathBuf::from("/about.html"))
+            .expect("known");
+        assert_eq!(
+            page,
+            vec![String::from("/about.html")].into_iter().collect()
+        );
+
+        assert_eq!(graph.affected_pages(&PathBuf::from("/unknown.js")), None);
+    }
+
+    #[test]
+    fn test_navigate_then_edit() {
+        let mut graph = DepGraph::default();
+        graph.add(&served("/style.css", Some("http://localhost:8090/")));
+        // following links from `/`
+        graph.add(&served("/about.html", Some("http://localhost:8090/")));
+        graph.add(&served("/blog/", Some("http://localhost:8090/")));
+        graph.add(&served(
+            "/about.js",
+            Some("http://localhost:8090/about.html"),
+        ));
+        // and back again
+        graph.add(&served("/", Some("http://localhost:8090/about.html")));
+
+        let page = graph
+            .affected_pages(&PathBuf::from("/about.html"))
+            .expect("known");
+        assert_eq!(
+            page,
+            vec![String::from("/about.html")].into_iter().collect()
+        );
+        assert_eq!(graph.affected_pages(&PathBuf::from("/blog/")), None);
+        let css = graph
+            .affected_pages(&PathBuf::from("/style.css"))
+            .expect("known");
+        assert_eq!(css, vec![String::from("/")].into_iter().collect());
+    }
+}
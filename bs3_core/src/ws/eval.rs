@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+use thiserror::Error;
+use tokio::sync::oneshot;
+
+///
+/// The outcome of an eval in a single browser
+///
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct EvalOutcome {
+    /// Client id
+    pub id: usize,
+    pub device: String,
+    pub value: Option<String>,
+    pub error: Option<String>,
+}
+
+#[derive(Error, Debug)]
+pub enum EvalError {
+    #[error("eval is disabled, restart bs3 with --allow-eval to enable it")]
+    Disabled,
+    #[error("no matching browsers are connected")]
+    NoClients,
+    #[error("the eval was cancelled before every browser replied")]
+    Cancelled,
+}
+
+#[derive(Debug)]
+struct PendingEval {
+    /// Client id -> device name, for every browser that hasn't replied yet
+    waiting: HashMap<usize, String>,
+    outcomes: Vec<EvalOutcome>,
+    tx: oneshot::Sender<Vec<EvalOutcome>>,
+}
+
+///
+/// Evals that were sent to browsers, but haven't been answered by all of them yet
+///
+#[derive(Debug, Default)]
+pub struct Evals {
+    next_id: u64,
+    pending: HashMap<u64, PendingEval>,
+}
+
+impl Evals {
+    ///
+    /// Start waiting on each of `targets` (client id -> device name),
+    /// `tx` receives the outcomes once they've all replied
+    ///
+    pub fn start(
+        &mut self,
+        targets: HashMap<usize, String>,
+        tx: oneshot::Sender<Vec<EvalOutcome>>,
+    ) -> u64 {
+        self.next_id += 1;
+        self.pending.insert(
+            self.next_id,
+            PendingEval {
+                waiting: targets,
+                outcomes: vec![],
+                tx,
+            },
+        );
+        self.next_id
+    }
+    pub fn result(
+        &mut self,
+        eval_id: u64,
+        client: usize,
+        value: Option<String>,
+        error: Option<String>,
+    ) {
+        if let Some(pending) = self.pending.get_mut(&eval_id) {
+            if let Some(device) = pending.waiting.remove(&client) {
+                pending.outcomes.push(EvalOutcome {
+                    id: client,
+                    device,
+                    value,
+                    error,
+                });
+            }
+        }
+        self.complete(eval_id);
+    }
+    /// A browser went away, don't wait on it any longer. Other browsers are still waited on
+    pub fn disconnect(&mut self, client: usize) {
+        let ids: Vec<u64> = self
+            .pending
+            .iter()
+            .filter(|(_, pending)| pending.waiting.contains_key(&client))
+            .map(|(eval_id, _)| *eval_id)
+            .collect();
+        for eval_id in ids {
+            self.result(eval_id, client, None, Some(String::from("disconnected")));
+        }
+    }
+    /// Give up on any browser that hasn't replied yet
+    pub fn timeout(&mut self, eval_id: u64) {
+        self.fail(eval_id, "timed out");
+    }
+    fn fail(&mut self, eval_id: u64, reason: &str) {
+        if let Some(pending) = self.pending.get_mut(&eval_id) {
+            let mut remaining: Vec<(usize, String)> = pending.waiting.drain().collect();
+            remaining.sort();
+            pending
+                .outcomes
+                .extend(remaining.into_iter().map(|(id, device)| EvalOutcome {
+                    id,
+                    device,
+                    value: None,
+                    error: Some(reason.to_owned()),
+                }));
+        }
+        self.complete(eval_id);
+    }
+    fn complete(&mut self, eval_id: u64) {
+        let done = self
+            .pending
+            .get(&eval_id)
+            .map(|pending| pending.waiting.is_empty())
+            .unwrap_or(false);
+        if !done {
+            return;
+        }
+        if let Some(pending) = self.pending.remove(&eval_id) {
+            // the requester may have gone away already, that's fine
+            let _ = pending.tx.send(pending.outcomes);
+        }
+    }
+}
+
+#[test]
+fn test_evals() {
+    let mut evals = Evals::default();
+    let (tx, mut rx) = oneshot::channel();
+    let targets = vec![
+        (1, String::from("Chrome 96 (macOS)")),
+        (2, String::from("client 2")),
+    ];
+    let eval_id = evals.start(targets.into_iter().collect(), tx);
+
+    evals.result(eval_id, 1, Some(String::from("\"bs3\"")), None);
+    // unknown clients are ignored
+    evals.result(eval_id, 3, Some(String::from("\"bs3\"")), None);
+    assert!(rx.try_recv().is_err());
+
+    evals.timeout(eval_id);
+    let outcomes = rx.try_recv().expect("complete");
+    assert_eq!(outcomes.len(), 2);
+    assert_eq!(outcomes[0].value.as_deref(), Some("\"bs3\""));
+    assert_eq!(outcomes[1].device, "client 2");
+    assert_eq!(outcomes[1].error.as_deref(), Some("timed out"));
+}
+
+#[test]
+fn test_evals_disconnect() {
+    let mut evals = Evals::default();
+    let (tx, mut rx) = oneshot::channel();
+    let targets = vec![(1, String::from("client 1")), (2, String::from("client 2"))];
+    let eval_id = evals.start(targets.into_iter().collect(), tx);
+
+    evals.disconnect(1);
+    assert!(rx.try_recv().is_err());
+
+    evals.result(eval_id, 2, Some(String::from("2")), None);
+    let outcomes = rx.try_recv().expect("complete");
+    assert_eq!(outcomes.len(), 2);
+    assert_eq!(outcomes[0].id, 1);
+    assert_eq!(outcomes[0].error.as_deref(), Some("disconnected"));
+    assert_eq!(outcomes[1].id, 2);
+    assert_eq!(outcomes[1].value.as_deref(), Some("2"));
+}
@@ -0,0 +1,159 @@
+use crate::ws::client::ClientMsg;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use structopt::StructOpt;
+
+/// How long after delivering an event to a browser, an identical event
+/// coming back from it is considered to be an echo
+const ECHO_WINDOW: Duration = Duration::from_millis(500);
+
+/// Scroll positions are proportional, so this is 1% of the scrollable distance
+const SCROLL_TOLERANCE: f64 = 0.01;
+
+///
+/// Which interactions are mirrored across all connected browsers
+///
+#[derive(StructOpt, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct GhostMode {
+    #[structopt(long = "no-ghost-scroll", parse(from_flag = std::ops::Not::not))]
+    #[serde(default = "crate::ws::ghost::enabled")]
+    pub scroll: bool,
+    #[structopt(long = "no-ghost-clicks", parse(from_flag = std::ops::Not::not))]
+    #[serde(default = "crate::ws::ghost::enabled")]
+    pub clicks: bool,
+    #[structopt(long = "no-ghost-location", parse(from_flag = std::ops::Not::not))]
+    #[serde(default = "crate::ws::ghost::enabled")]
+    pub location: bool,
+    #[structopt(flatten)]
+    #[serde(default)]
+    pub forms: GhostForms,
+}
+
+#[derive(StructOpt, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct GhostForms {
+    #[structopt(long = "no-ghost-inputs", parse(from_flag = std::ops::Not::not))]
+    #[serde(default = "crate::ws::ghost::enabled")]
+    pub inputs: bool,
+    #[structopt(long = "no-ghost-submit", parse(from_flag = std::ops::Not::not))]
+    #[serde(default = "crate::ws::ghost::enabled")]
+    pub submit: bool,
+    #[structopt(long = "no-ghost-toggles", parse(from_flag = std::ops::Not::not))]
+    #[serde(default = "crate::ws::ghost::enabled")]
+    pub toggles: bool,
+}
+
+pub fn enabled() -> bool {
+    true
+}
+
+impl Default for GhostMode {
+    fn default() -> Self {
+        Self {
+            scroll: true,
+            clicks: true,
+            location: true,
+            forms: GhostForms::default(),
+        }
+    }
+}
+
+impl Default for GhostForms {
+    fn default() -> Self {
+        Self {
+            inputs: true,
+            submit: true,
+            toggles: true,
+        }
+    }
+}
+
+impl GhostMode {
+    ///
+    /// Whether `msg` should be relayed to other browsers.
+    /// `None` means it's not a ghost-mode message at all
+    ///
+    pub fn allows(&self, msg: &ClientMsg) -> Option<bool> {
+        match msg {
+            ClientMsg::Scroll(..) => Some(self.scroll),
+            ClientMsg::Click(..) => Some(self.clicks),
+            ClientMsg::Navigate(..) => Some(self.location),
+            ClientMsg::Input(..) => Some(self.forms.inputs),
+            ClientMsg::Submit(..) => Some(self.forms.submit),
+            ClientMsg::Toggle(..) => Some(self.forms.toggles),
+            _ => None,
+        }
+    }
+}
+
+///
+/// Browsers that apply a remote scroll/click/input will often emit the same event
+/// themselves. This remembers the last event delivered to each session so that those
+/// echoes are not relayed back out again
+///
+#[derive(Debug, Default)]
+pub struct EchoGuard {
+    delivered: HashMap<usize, (ClientMsg, Instant)>,
+}
+
+impl EchoGuard {
+    pub fn record(&mut self, id: usize, msg: &ClientMsg, now: Instant) {
+        self.delivered.insert(id, (msg.clone(), now));
+    }
+    pub fn is_echo(&self, id: usize, msg: &ClientMsg, now: Instant) -> bool {
+        match self.delivered.get(&id) {
+            Some((prev, at)) if now.duration_since(*at) < ECHO_WINDOW => same_event(prev, msg),
+            _ => false,
+        }
+    }
+    pub fn remove(&mut self, id: usize) {
+        self.delivered.remove(&id);
+    }
+}
+
+fn same_event(prev: &ClientMsg, next: &ClientMsg) -> bool {
+    match (prev, next) {
+        (ClientMsg::Scroll(prev), ClientMsg::Scroll(next)) => {
+            (prev.x - next.x).abs() < SCROLL_TOLERANCE && (prev.y - next.y).abs() < SCROLL_TOLERANCE
+        }
+        (prev, next) => prev == next,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ws::client::{ClickMsg, ScrollMsg};
+
+    #[test]
+    fn test_echo_guard() {
+        let now = Instant::now();
+        let mut guard = EchoGuard::default();
+        let scroll = ClientMsg::Scroll(ScrollMsg { x: 0.0, y: 0.5 });
+        guard.record(1, &scroll, now);
+
+        let echo = ClientMsg::Scroll(ScrollMsg { x: 0.0, y: 0.505 });
+        assert!(guard.is_echo(1, &echo, now + Duration::from_millis(50)));
+        assert!(!guard.is_echo(2, &echo, now + Duration::from_millis(50)));
+        assert!(!guard.is_echo(1, &echo, now + Duration::from_secs(1)));
+
+        let moved = ClientMsg::Scroll(ScrollMsg { x: 0.0, y: 0.8 });
+        assert!(!guard.is_echo(1, &moved, now));
+
+        let click = ClientMsg::Click(ClickMsg {
+            selector: String::from("#submit"),
+        });
+        assert!(!guard.is_echo(1, &click, now));
+    }
+
+    #[test]
+    fn test_deserialize() -> anyhow::Result<()> {
+        let input = r#"{"clicks": false, "forms": {"submit": false}}"#;
+        let ghost: GhostMode = serde_json::from_str(input)?;
+        assert!(ghost.scroll);
+        assert!(!ghost.clicks);
+        assert!(ghost.forms.inputs);
+        assert!(!ghost.forms.submit);
+        Ok(())
+    }
+}
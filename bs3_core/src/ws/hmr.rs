@@ -0,0 +1,338 @@
+use crate::ws::client::ServedFile;
+use crate::ws::dep_graph::{page_path, web_path};
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+///
+/// Matches static imports/re-exports (`import x from "./a.js"`, `export * from "./b.js"`),
+/// side-effect imports (`import "./c.js"`) and dynamic imports (`import("./d.js")`)
+///
+const IMPORT_PATTERN: &str =
+    r#"\b(?:import|export)\b[^"'();]*?\bfrom\s*["']([^"']+)["']|\bimport\s*\(?\s*["']([^"']+)["']"#;
+
+///
+/// Syntax that's only valid in ES modules: `import`/`export` statements and `import.meta`.
+/// Checked once comments & strings are removed, see `code_only`
+///
+const MODULE_SYNTAX_PATTERN: &str =
+    r#"(?m)(?:^|[;})])\s*(?:import\s*[\w*{"']|export\b)|\bimport\s*\.\s*meta\b"#;
+
+///
+/// Which ES modules import which, and which of them accept hot updates.
+///
+/// Edges come from two places: the `referer` of each served module, and the
+/// `import` statements found in the module's source. Pages (eg: `/index.html`)
+/// are importers too, but they can never accept an update
+///
+#[derive(Debug)]
+pub struct ModuleGraph {
+    /// module -> the modules & pages that import it
+    importers: HashMap<String, HashSet<String>>,
+    /// module -> what its source imports, so those edges can be replaced when it changes
+    imports: HashMap<String, HashSet<String>>,
+    /// module -> session -> the modules whose updates it accepts, including itself when
+    /// self-accepting. Kept per session, since each browser registers them as modules run
+    accepts: HashMap<String, HashMap<usize, HashSet<String>>>,
+    import_re: Regex,
+}
+
+impl Default for ModuleGraph {
+    fn default() -> Self {
+        Self {
+            importers: HashMap::new(),
+            imports: HashMap::new(),
+            accepts: HashMap::new(),
+            import_re: Regex::new(IMPORT_PATTERN).expect("valid import pattern"),
+        }
+    }
+}
+
+impl ModuleGraph {
+    pub fn is_module(web_path: &Path) -> bool {
+        matches!(
+            web_path.extension().and_then(|ext| ext.to_str()),
+            Some("js") | Some("mjs")
+        )
+    }
+    ///
+    /// Whether `source` is an ES module rather than a classic script. Only module syntax in
+    /// code counts, not in comments or strings
+    ///
+    pub fn is_es_module(source: &str) -> bool {
+        lazy_static::lazy_static! {
+            static ref MODULE_SYNTAX: Regex =
+                Regex::new(MODULE_SYNTAX_PATTERN).expect("valid module syntax pattern");
+        }
+        MODULE_SYNTAX.is_match(&code_only(source))
+    }
+    /// Whether the source of `module` has been parsed already
+    pub fn is_parsed(&self, module: &str) -> bool {
+        self.imports.contains_key(module)
+    }
+    /// A module was served, whatever requested it imports it
+    pub fn add_referer(&mut self, served: &ServedFile) {
+        if let Some(importer) = served.referer.as_deref().and_then(page_path) {
+            let module = web_path(&served.web_path);
+            if importer != module {
+                self.importers
+                    .entry(module)
+                    .or_insert_with(HashSet::new)
+                    .insert(importer);
+            }
+        }
+    }
+    /// Replace the imports of `module` with the ones found in `source`
+    pub fn parse(&mut self, module: &str, source: &str) {
+        let found: HashSet<String> = self
+            .import_re
+            .captures_iter(source)
+            .filter_map(|caps| caps.get(1).or_else(|| caps.get(2)))
+            .filter_map(|specifier| resolve(module, specifier.as_str()))
+            .collect();
+        if let Some(previous) = self.imports.get(module) {
+            for stale in previous.difference(&found) {
+                if let Some(importers) = self.importers.get_mut(stale) {
+                    importers.remove(module);
+                }
+            }
+        }
+        for dep in &found {
+            self.importers
+                .entry(dep.to_owned())
+                .or_insert_with(HashSet::new)
+                .insert(module.to_owned());
+        }
+        self.imports.insert(module.to_owned(), found);
+    }
+    ///
+    /// `import.meta.hot.accept()` was called in `module`, in the browser behind `session`,
+    /// with no `deps` when it accepts itself
+    ///
+    pub fn accept(&mut self, session: usize, module: &str, deps: &[String]) {
+        let accepts = self
+            .accepts
+            .entry(module.to_owned())
+            .or_insert_with(HashMap::new)
+            .entry(session)
+            .or_insert_with(HashSet::new);
+        if deps.is_empty() {
+            accepts.insert(module.to_owned());
+        } else {
+            accepts.extend(deps.iter().cloned());
+        }
+    }
+    ///
+    /// A new version of `module` is being imported, what the old one accepted no longer
+    /// applies. The new version registers its own accepts as it runs
+    ///
+    pub fn replaced(&mut self, module: &str) {
+        self.accepts.remove(module);
+    }
+    /// A browser went away, along with the modules it was running
+    pub fn disconnect(&mut self, session: usize) {
+        for sessions in self.accepts.values_mut() {
+            sessions.remove(&session);
+        }
+        self.accepts.retain(|_, sessions| !sessions.is_empty());
+    }
+    ///
+    /// Whether a change to `module` can be applied by re-importing it. That's the case when
+    /// it accepts itself, or when every importer accepts it.
+    ///
+    /// Updates are not propagated any further up the graph: browsers cache modules by URL,
+    /// so an importer that was re-imported would still see the old version of its imports
+    ///
+    pub fn can_hot_update(&self, module: &str) -> bool {
+        if self.accepts_update(module, module) {
+            return true;
+        }
+        match self.importers.get(module) {
+            Some(importers) if !importers.is_empty() => importers
+                .iter()
+                .all(|importer| self.accepts_update(importer, module)),
+            _ => false,
+        }
+    }
+    fn accepts_update(&self, importer: &str, module: &str) -> bool {
+        self.accepts
+            .get(importer)
+            .map(|sessions| sessions.values().any(|accepts| accepts.contains(module)))
+            .unwrap_or(false)
+    }
+}
+
+///
+/// `source` with comments removed, and the contents of strings & template literals emptied
+///
+fn code_only(source: &str) -> String {
+    let mut out = String::with_capacity(source.len());
+    let mut chars = source.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '/' if chars.peek() == Some(&'/') => {
+                while let Some(&next) = chars.peek() {
+                    if next == '\n' {
+                        break;
+                    }
+                    chars.next();
+                }
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut prev = '\0';
+                for next in chars.by_ref() {
+                    if prev == '*' && next == '/' {
+                        break;
+                    }
+                    prev = next;
+                }
+                out.push(' ');
+            }
+            '"' | '\'' | '`' => {
+                while let Some(next) = chars.next() {
+                    if next == '\\' {
+                        chars.next();
+                    } else if next == c {
+                        break;
+                    }
+                }
+                out.push(c);
+                out.push(c);
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+///
+/// Resolve an import specifier against the module it appears in.
+///
+/// Bare specifiers (eg: `react`) and full URLs aren't served by bs3, so they're skipped
+///
+/// eg: `./b.js` in `/src/a.js` -> `/src/b.js`
+///
+pub fn resolve(module: &str, specifier: &str) -> Option<String> {
+    if !(specifier.starts_with("./") || specifier.starts_with("../") || specifier.starts_with('/'))
+    {
+        return None;
+    }
+    let base = url::Url::parse("http://localhost")
+        .ok()?
+        .join(module)
+        .ok()?;
+    base.join(specifier).ok().map(|url| url.path().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    const APP: &str = r#"
+        import { render } from "./render.js";
+        import Counter, {
+            increment,
+        } from './components/counter.js';
+        import "../polyfills.js";
+        export * from "/shared/utils.mjs";
+        import React from "react";
+        const lazy = () => import("./lazy.js");
+        if (import.meta.hot) {
+            import.meta.hot.accept("./render.js", () => {});
+        }
+    "#;
+
+    #[test]
+    fn test_parse_imports() {
+        let mut graph = ModuleGraph::default();
+        graph.parse("/src/app.js", APP);
+        let mut imports: Vec<&String> = graph.imports["/src/app.js"].iter().collect();
+        imports.sort();
+        assert_eq!(
+            imports,
+            vec![
+                "/polyfills.js",
+                "/shared/utils.mjs",
+                "/src/components/counter.js",
+                "/src/lazy.js",
+                "/src/render.js",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_can_hot_update() {
+        let mut graph = ModuleGraph::default();
+        graph.add_referer(&ServedFile {
+            path: PathBuf::from("/www/src/app.js"),
+            web_path: PathBuf::from("/src/app.js"),
+            referer: Some(String::from("http://localhost:8090/")),
+        });
+        graph.parse("/src/app.js", APP);
+
+        // nothing has accepted anything yet
+        assert!(!graph.can_hot_update("/src/render.js"));
+
+        graph.accept(1, "/src/app.js", &[String::from("/src/render.js")]);
+        assert!(graph.can_hot_update("/src/render.js"));
+        assert!(!graph.can_hot_update("/src/lazy.js"));
+
+        // app.js is imported by the page, so it can only update itself
+        assert!(!graph.can_hot_update("/src/app.js"));
+        graph.accept(1, "/src/app.js", &[]);
+        assert!(graph.can_hot_update("/src/app.js"));
+
+        // once render.js has another importer, that one must accept it too
+        graph.parse("/src/other.js", "import { render } from './render.js'");
+        assert!(!graph.can_hot_update("/src/render.js"));
+
+        // and stale imports are forgotten
+        graph.parse("/src/other.js", "");
+        assert!(graph.can_hot_update("/src/render.js"));
+    }
+
+    #[test]
+    fn test_stale_accepts() {
+        let mut graph = ModuleGraph::default();
+        graph.parse("/src/app.js", APP);
+        graph.accept(1, "/src/app.js", &[String::from("/src/render.js")]);
+        graph.accept(2, "/src/app.js", &[String::from("/src/render.js")]);
+
+        // still accepted by the other browser
+        graph.disconnect(1);
+        assert!(graph.can_hot_update("/src/render.js"));
+        graph.disconnect(2);
+        assert!(!graph.can_hot_update("/src/render.js"));
+
+        // a new version of app.js may have dropped its `accept()`
+        graph.accept(3, "/src/app.js", &[]);
+        assert!(graph.can_hot_update("/src/app.js"));
+        graph.replaced("/src/app.js");
+        assert!(!graph.can_hot_update("/src/app.js"));
+    }
+
+    #[test]
+    fn test_is_es_module() {
+        assert!(ModuleGraph::is_es_module(APP));
+        assert!(ModuleGraph::is_es_module("export default 1;"));
+        assert!(ModuleGraph::is_es_module(
+            "if (import.meta.hot) { import.meta.hot.accept(); }"
+        ));
+        assert!(ModuleGraph::is_es_module(
+            "const a = 1;import{b}from'./b.js'"
+        ));
+        assert!(ModuleGraph::is_es_module("import './polyfills.js';"));
+        // classic scripts
+        assert!(!ModuleGraph::is_es_module(
+            "// import.meta.hot is only set for modules\nwindow.a = 1;"
+        ));
+        assert!(!ModuleGraph::is_es_module(
+            "/* export default */ console.log('import.meta.hot', `import x from`);"
+        ));
+        assert!(!ModuleGraph::is_es_module(
+            "const lazy = () => import('./lazy.js'); var important = 1;"
+        ));
+    }
+}
@@ -0,0 +1,333 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::reload_rules::{ReloadRules, RuleAction};
+use crate::ws::client::FsNotify;
+use actix::*;
+use actix_web::{web, Error, HttpRequest, HttpResponse};
+use actix_web_actors::ws;
+use rand::{self, rngs::ThreadRng, Rng};
+
+/// The only protocol spoken, see http://livereload.com/api/protocol/
+pub const PROTOCOL_V7: &str = "http://livereload.com/protocols/official-7";
+
+/// How often heartbeat pings are sent
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+/// How long before lack of client response causes a timeout
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+
+///
+/// Commands sent to LiveReload clients
+///
+#[derive(Message, Debug, Clone, PartialEq, serde::Serialize)]
+#[rtype(result = "()")]
+#[serde(tag = "command", rename_all = "lowercase")]
+pub enum LrCommand {
+    Hello {
+        protocols: Vec<String>,
+        #[serde(rename = "serverName")]
+        server_name: String,
+    },
+    Reload {
+        path: String,
+        #[serde(rename = "liveCSS")]
+        live_css: bool,
+        #[serde(rename = "liveImg")]
+        live_img: bool,
+    },
+    Alert {
+        message: String,
+    },
+}
+
+///
+/// Commands sent by LiveReload clients. Only `hello` needs a reply,
+/// everything else (`info`, `url`...) is informational
+///
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+#[serde(tag = "command", rename_all = "lowercase")]
+enum LrIncoming {
+    Hello {
+        #[serde(default)]
+        protocols: Vec<String>,
+    },
+    #[serde(other)]
+    Other,
+}
+
+/// A LiveReload client connected
+#[derive(Message)]
+#[rtype(usize)]
+pub struct LrConnect {
+    pub addr: Recipient<LrCommand>,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct LrDisconnect {
+    pub id: usize,
+}
+
+/// Show a message in every LiveReload client, eg: a failed build
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct LrAlert {
+    pub message: String,
+}
+
+///
+/// Speaks the LiveReload v7 protocol, so that the existing browser extensions
+/// and framework integrations can use bs3 instead of a separate livereload server.
+///
+/// Driven by the same `FsNotify` events as `WsServer`, with the same reload rules.
+/// Error overlays are shown as alerts.
+///
+/// Only the protocol is supported - `livereload.js` itself isn't served, so pages
+/// that include it need a LiveReload extension, or the bs3 client instead
+///
+pub struct LiveReloadServer {
+    sessions: HashMap<usize, Recipient<LrCommand>>,
+    reload_rules: ReloadRules,
+    rng: ThreadRng,
+}
+
+impl LiveReloadServer {
+    pub fn new(reload_rules: ReloadRules) -> Self {
+        Self {
+            sessions: HashMap::new(),
+            reload_rules,
+            rng: rand::thread_rng(),
+        }
+    }
+
+    fn send_all(&self, command: LrCommand) {
+        for addr in self.sessions.values() {
+            let _ = addr.do_send(command.clone());
+        }
+    }
+}
+
+impl Actor for LiveReloadServer {
+    type Context = Context<Self>;
+}
+
+impl Handler<LrConnect> for LiveReloadServer {
+    type Result = usize;
+
+    fn handle(&mut self, msg: LrConnect, _: &mut Context<Self>) -> Self::Result {
+        let id = self.rng.gen::<usize>();
+        log::trace!("+ livereload client connected = ({})", id);
+        self.sessions.insert(id, msg.addr);
+        id
+    }
+}
+
+impl Handler<LrDisconnect> for LiveReloadServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: LrDisconnect, _: &mut Context<Self>) {
+        log::trace!("- livereload client disconnected {}", msg.id);
+        self.sessions.remove(&msg.id);
+    }
+}
+
+impl Handler<LrAlert> for LiveReloadServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: LrAlert, _: &mut Context<Self>) {
+        self.send_all(LrCommand::Alert {
+            message: msg.message,
+        });
+    }
+}
+
+impl Handler<FsNotify> for LiveReloadServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: FsNotify, _: &mut Context<Self>) {
+        if let Some(command) = reload_command(&self.reload_rules, &msg) {
+            self.send_all(command);
+        }
+    }
+}
+
+///
+/// LiveReload clients decide for themselves how to apply a change, `liveCSS` & `liveImg`
+/// only allow them to try. So `inject` rules allow it, `reload` rules don't
+///
+fn reload_command(rules: &ReloadRules, msg: &FsNotify) -> Option<LrCommand> {
+    let live = match rules.action(&msg.item) {
+        RuleAction::Ignore => return None,
+        RuleAction::Inject => true,
+        RuleAction::Reload => false,
+    };
+    Some(LrCommand::Reload {
+        path: msg.item.web_path.to_string_lossy().to_string(),
+        live_css: live,
+        live_img: live,
+    })
+}
+
+/// Entry point for `/livereload`
+pub async fn livereload_route(
+    req: HttpRequest,
+    stream: web::Payload,
+    srv: web::Data<Addr<LiveReloadServer>>,
+) -> Result<HttpResponse, Error> {
+    ws::start(
+        LrSession {
+            id: 0,
+            hb: Instant::now(),
+            addr: srv.get_ref().clone(),
+        },
+        &req,
+        stream,
+    )
+}
+
+struct LrSession {
+    id: usize,
+    hb: Instant,
+    addr: Addr<LiveReloadServer>,
+}
+
+impl Actor for LrSession {
+    type Context = ws::WebsocketContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.hb(ctx);
+        self.addr
+            .send(LrConnect {
+                addr: ctx.address().recipient(),
+            })
+            .into_actor(self)
+            .then(|res, act, ctx| {
+                match res {
+                    Ok(id) => act.id = id,
+                    _ => ctx.stop(),
+                }
+                fut::ready(())
+            })
+            .wait(ctx);
+    }
+
+    fn stopping(&mut self, _: &mut Self::Context) -> Running {
+        self.addr.do_send(LrDisconnect { id: self.id });
+        Running::Stop
+    }
+}
+
+impl Handler<LrCommand> for LrSession {
+    type Result = ();
+
+    fn handle(&mut self, msg: LrCommand, ctx: &mut Self::Context) {
+        match serde_json::to_string(&msg) {
+            Ok(json) => ctx.text(json),
+            Err(e) => log::error!("could not serialize a livereload command: {}", e),
+        }
+    }
+}
+
+impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for LrSession {
+    fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
+        let msg = match msg {
+            Err(_) => {
+                ctx.stop();
+                return;
+            }
+            Ok(msg) => msg,
+        };
+        match msg {
+            ws::Message::Ping(msg) => {
+                self.hb = Instant::now();
+                ctx.pong(&msg);
+            }
+            ws::Message::Pong(_) => self.hb = Instant::now(),
+            ws::Message::Text(text) => match serde_json::from_str::<LrIncoming>(&text) {
+                Ok(LrIncoming::Hello { protocols }) => {
+                    if !protocols.iter().any(|protocol| protocol == PROTOCOL_V7) {
+                        log::debug!("livereload client without v7 support {:?}", protocols);
+                    }
+                    ctx.notify(LrCommand::Hello {
+                        protocols: vec![PROTOCOL_V7.to_string()],
+                        server_name: String::from("bs3"),
+                    });
+                }
+                Ok(LrIncoming::Other) => log::trace!("livereload client said {}", text),
+                Err(e) => log::debug!("invalid livereload message {}: {}", text, e),
+            },
+            ws::Message::Close(reason) => {
+                ctx.close(reason);
+                ctx.stop();
+            }
+            ws::Message::Binary(_) | ws::Message::Continuation(_) => ctx.stop(),
+            ws::Message::Nop => (),
+        }
+    }
+}
+
+impl LrSession {
+    fn hb(&self, ctx: &mut ws::WebsocketContext<Self>) {
+        ctx.run_interval(HEARTBEAT_INTERVAL, |act, ctx| {
+            if Instant::now().duration_since(act.hb) > CLIENT_TIMEOUT {
+                ctx.stop();
+                return;
+            }
+            ctx.ping(b"");
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ws::client::ServedFile;
+    use std::path::PathBuf;
+
+    fn notify(web_path: &str) -> FsNotify {
+        FsNotify::new(ServedFile {
+            web_path: PathBuf::from(web_path),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_reload_command() -> anyhow::Result<()> {
+        let rules = ReloadRules::default();
+        let css = reload_command(&rules, &notify("/css/style.css")).expect("reload");
+        assert_eq!(
+            serde_json::to_value(&css)?,
+            serde_json::json!({
+                "command": "reload",
+                "path": "/css/style.css",
+                "liveCSS": true,
+                "liveImg": true
+            })
+        );
+        let html = reload_command(&rules, &notify("/index.html"));
+        assert!(matches!(
+            html,
+            Some(LrCommand::Reload {
+                live_css: false,
+                ..
+            })
+        ));
+        assert_eq!(reload_command(&rules, &notify("/app.js.map")), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_incoming() -> anyhow::Result<()> {
+        let hello =
+            r#"{"command":"hello","protocols":["http://livereload.com/protocols/official-7"]}"#;
+        assert_eq!(
+            serde_json::from_str::<LrIncoming>(hello)?,
+            LrIncoming::Hello {
+                protocols: vec![PROTOCOL_V7.to_string()]
+            }
+        );
+        let info = r#"{"command":"info","url":"http://localhost:8090/","plugins":{}}"#;
+        assert_eq!(serde_json::from_str::<LrIncoming>(info)?, LrIncoming::Other);
+        Ok(())
+    }
+}
@@ -1,5 +1,31 @@
 pub mod client;
 #[cfg(not(target_arch = "wasm32"))]
+pub mod console;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod dep_graph;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod eval;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod ghost;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod hmr;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod livereload;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod outbox;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod recording;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod registry;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod relay;
+#[cfg(not(target_arch = "wasm32"))]
 pub mod server;
 #[cfg(not(target_arch = "wasm32"))]
+pub mod session;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod sse_session;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod user_agent;
+#[cfg(not(target_arch = "wasm32"))]
 pub mod ws_session;
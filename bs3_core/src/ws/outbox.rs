@@ -0,0 +1,151 @@
+use crate::ws::client::ClientMsg;
+use std::collections::{HashMap, VecDeque};
+use std::ops::AddAssign;
+
+/// The most messages held for a single session whose mailbox is full
+const MAX_QUEUED: usize = 32;
+
+///
+/// How many messages a session never received because it couldn't keep up
+///
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, serde::Serialize)]
+pub struct OutboxStats {
+    /// Scroll positions replaced by a newer one before they were delivered
+    pub coalesced: u64,
+    /// Messages discarded because the queue was full
+    pub dropped: u64,
+}
+
+impl OutboxStats {
+    pub fn is_empty(&self) -> bool {
+        self.coalesced == 0 && self.dropped == 0
+    }
+}
+
+impl AddAssign for OutboxStats {
+    fn add_assign(&mut self, other: Self) {
+        self.coalesced += other.coalesced;
+        self.dropped += other.dropped;
+    }
+}
+
+///
+/// Bounded, per-session queues for messages that couldn't be delivered
+/// straight away because the session's mailbox was full, eg: a backgrounded
+/// mobile tab during a flood of scroll events
+///
+#[derive(Debug, Default)]
+pub struct Outbox {
+    queues: HashMap<usize, VecDeque<ClientMsg>>,
+}
+
+impl Outbox {
+    ///
+    /// Only high-frequency ghost-mode messages may be queued, coalesced or dropped.
+    /// Everything else (reloads, injections, evals...) is always delivered immediately
+    ///
+    pub fn is_deferrable(msg: &ClientMsg) -> bool {
+        matches!(
+            msg,
+            ClientMsg::Scroll(..)
+                | ClientMsg::Click(..)
+                | ClientMsg::Input(..)
+                | ClientMsg::Toggle(..)
+                | ClientMsg::Submit(..)
+                | ClientMsg::Navigate(..)
+                | ClientMsg::Connect
+                | ClientMsg::Disconnect
+        )
+    }
+    pub fn has_queued(&self, id: usize) -> bool {
+        self.queues
+            .get(&id)
+            .map(|queue| !queue.is_empty())
+            .unwrap_or(false)
+    }
+    /// Queue `msg` behind anything already waiting for this session
+    pub fn push(&mut self, id: usize, msg: ClientMsg) -> OutboxStats {
+        let queue = self.queues.entry(id).or_insert_with(VecDeque::new);
+        let mut stats = OutboxStats::default();
+        if let ClientMsg::Scroll(..) = msg {
+            let before = queue.len();
+            queue.retain(|queued| !matches!(queued, ClientMsg::Scroll(..)));
+            stats.coalesced += (before - queue.len()) as u64;
+        }
+        while queue.len() >= MAX_QUEUED {
+            queue.pop_front();
+            stats.dropped += 1;
+        }
+        queue.push_back(msg);
+        stats
+    }
+    pub fn pop(&mut self, id: usize) -> Option<ClientMsg> {
+        self.queues.get_mut(&id).and_then(|queue| queue.pop_front())
+    }
+    /// Put back a message that still couldn't be delivered
+    pub fn unpop(&mut self, id: usize, msg: ClientMsg) {
+        self.queues
+            .entry(id)
+            .or_insert_with(VecDeque::new)
+            .push_front(msg);
+    }
+    /// Sessions that have messages waiting
+    pub fn ids(&self) -> Vec<usize> {
+        self.queues
+            .iter()
+            .filter(|(_, queue)| !queue.is_empty())
+            .map(|(id, _)| *id)
+            .collect()
+    }
+    pub fn remove(&mut self, id: usize) {
+        self.queues.remove(&id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ws::client::{ClickMsg, ScrollMsg};
+
+    fn click(n: usize) -> ClientMsg {
+        ClientMsg::Click(ClickMsg {
+            selector: format!("#button-{}", n),
+        })
+    }
+
+    #[test]
+    fn test_coalesce_scroll() {
+        let mut outbox = Outbox::default();
+        outbox.push(1, ClientMsg::Scroll(ScrollMsg { x: 0.0, y: 0.1 }));
+        outbox.push(1, click(1));
+        let stats = outbox.push(1, ClientMsg::Scroll(ScrollMsg { x: 0.0, y: 0.9 }));
+        assert_eq!(stats.coalesced, 1);
+
+        assert_eq!(outbox.pop(1), Some(click(1)));
+        assert_eq!(
+            outbox.pop(1),
+            Some(ClientMsg::Scroll(ScrollMsg { x: 0.0, y: 0.9 }))
+        );
+        assert_eq!(outbox.pop(1), None);
+        assert!(!outbox.has_queued(1));
+    }
+
+    #[test]
+    fn test_bounded() {
+        let mut outbox = Outbox::default();
+        let mut stats = OutboxStats::default();
+        for n in 0..MAX_QUEUED + 3 {
+            stats += outbox.push(1, click(n));
+        }
+        assert_eq!(stats.dropped, 3);
+        assert_eq!(outbox.pop(1), Some(click(3)));
+    }
+
+    #[test]
+    fn test_reloads_are_never_deferred() {
+        assert!(!Outbox::is_deferrable(&ClientMsg::Reload {
+            reason: String::from("index.html changed")
+        }));
+        assert!(Outbox::is_deferrable(&click(0)));
+    }
+}
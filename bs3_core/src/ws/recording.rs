@@ -0,0 +1,161 @@
+use crate::ws::client::ClientMsg;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+/// Recordings are only ever saved to & loaded from here, relative to the working directory
+pub const RECORDINGS_DIR: &str = ".bs3/recordings";
+
+///
+/// A ghost-mode walkthrough, eg: navigate, scroll, click, input
+///
+/// Saved as JSON so that it can be replayed later, or tweaked by hand
+///
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Recording {
+    pub events: Vec<RecordedEvent>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct RecordedEvent {
+    /// Milliseconds since the recording was started
+    pub at_ms: u64,
+    pub msg: ClientMsg,
+}
+
+#[derive(Error, Debug)]
+pub enum RecordingError {
+    #[error("nothing is being recorded")]
+    NotRecording,
+    #[error("could not access the recording file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid recording: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("event {0} of the recording is not a ghost-mode event, it can't be replayed")]
+    NotGhostMode(usize),
+    #[error(
+        "`{0}` is not a file name, recordings can only be saved in {}",
+        RECORDINGS_DIR
+    )]
+    InvalidName(String),
+}
+
+///
+/// Where the recording called `name` lives inside `dir`. Only a plain file name is
+/// accepted - directories, `..` & absolute paths are rejected, so that the api
+/// can't be used to read or overwrite anything else
+///
+pub fn recording_path(dir: &Path, name: &str) -> Result<PathBuf, RecordingError> {
+    let mut components = Path::new(name).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(file_name)), None) => Ok(dir.join(file_name)),
+        _ => Err(RecordingError::InvalidName(name.to_owned())),
+    }
+}
+
+impl Recording {
+    pub fn load(path: &Path) -> Result<Self, RecordingError> {
+        let json = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&json)?)
+    }
+    pub fn save(&self, path: &Path) -> Result<(), RecordingError> {
+        let json = serde_json::to_string_pretty(self)?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, json)?;
+        Ok(())
+    }
+    /// Each message, along with how long after the start it should be sent
+    pub fn schedule(&self) -> impl Iterator<Item = (Duration, &ClientMsg)> {
+        self.events
+            .iter()
+            .map(|event| (Duration::from_millis(event.at_ms), &event.msg))
+    }
+}
+
+///
+/// Collects events while a recording is in progress
+///
+#[derive(Debug)]
+pub struct Recorder {
+    started: Instant,
+    recording: Recording,
+}
+
+impl Recorder {
+    pub fn new(started: Instant) -> Self {
+        Self {
+            started,
+            recording: Recording::default(),
+        }
+    }
+    pub fn push(&mut self, msg: &ClientMsg, now: Instant) {
+        self.recording.events.push(RecordedEvent {
+            at_ms: now.duration_since(self.started).as_millis() as u64,
+            msg: msg.clone(),
+        });
+    }
+    pub fn finish(self) -> Recording {
+        self.recording
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ws::client::{ClickMsg, ScrollMsg};
+
+    #[test]
+    fn test_record() -> anyhow::Result<()> {
+        let start = Instant::now();
+        let mut recorder = Recorder::new(start);
+        recorder.push(&ClientMsg::Scroll(ScrollMsg { x: 0.0, y: 0.5 }), start);
+        recorder.push(
+            &ClientMsg::Click(ClickMsg {
+                selector: String::from("#submit"),
+            }),
+            start + Duration::from_millis(1500),
+        );
+        let recording = recorder.finish();
+
+        let json = serde_json::to_string(&recording)?;
+        let parsed: Recording = serde_json::from_str(&json)?;
+        assert_eq!(parsed, recording);
+
+        let schedule: Vec<Duration> = parsed.schedule().map(|(at, _)| at).collect();
+        assert_eq!(
+            schedule,
+            vec![Duration::from_millis(0), Duration::from_millis(1500)]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_recording_path() -> anyhow::Result<()> {
+        let dir = Path::new(RECORDINGS_DIR);
+        assert_eq!(
+            recording_path(dir, "checkout.json")?,
+            PathBuf::from(".bs3/recordings/checkout.json")
+        );
+        for name in &[
+            "",
+            "..",
+            "../checkout.json",
+            "/etc/passwd",
+            "nested/checkout.json",
+            "./checkout.json",
+        ] {
+            assert!(
+                matches!(
+                    recording_path(dir, name),
+                    Err(RecordingError::InvalidName(_))
+                ),
+                "{}",
+                name
+            );
+        }
+        Ok(())
+    }
+}
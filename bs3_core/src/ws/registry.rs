@@ -0,0 +1,171 @@
+use crate::ws::client::ViewportMsg;
+use crate::ws::dep_graph::page_path;
+use crate::ws::outbox::OutboxStats;
+use crate::ws::user_agent;
+use std::collections::{HashMap, VecDeque};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How many disconnected clients are remembered
+const MAX_DEPARTED: usize = 20;
+
+///
+/// Everything that's known about a connected browser
+///
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct ClientInfo {
+    pub id: usize,
+    pub user_agent: Option<String>,
+    pub browser: Option<String>,
+    pub os: Option<String>,
+    /// What the client bundle supports, as sent in its handshake
+    pub features: Vec<String>,
+    pub viewport: Option<ViewportMsg>,
+    /// The full URL of the page being shown
+    pub url: Option<String>,
+    /// The path-only version of `url`
+    pub page: Option<String>,
+    pub room: String,
+    /// All times are milliseconds since the unix epoch
+    pub connected_at: u64,
+    pub last_heartbeat: u64,
+    pub disconnected_at: Option<u64>,
+    /// Messages this client missed because it couldn't keep up
+    pub outbox: OutboxStats,
+}
+
+impl ClientInfo {
+    ///
+    /// A short, human-friendly name for this device, eg: `Chrome 96 (Android)`
+    ///
+    pub fn device_name(&self) -> String {
+        match (&self.browser, &self.os) {
+            (Some(browser), Some(os)) => format!("{} ({})", browser, os),
+            (Some(browser), None) => browser.to_owned(),
+            (None, Some(os)) => os.to_owned(),
+            (None, None) => format!("client {}", self.id),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct ClientList {
+    pub connected: Vec<ClientInfo>,
+    /// The most recently disconnected clients, newest first
+    pub disconnected: Vec<ClientInfo>,
+}
+
+#[derive(Debug, Default)]
+pub struct Registry {
+    clients: HashMap<usize, ClientInfo>,
+    departed: VecDeque<ClientInfo>,
+}
+
+impl Registry {
+    pub fn connect(&mut self, id: usize, room: &str, now: u64) {
+        self.clients.insert(
+            id,
+            ClientInfo {
+                id,
+                user_agent: None,
+                browser: None,
+                os: None,
+                features: vec![],
+                viewport: None,
+                url: None,
+                page: None,
+                room: room.to_owned(),
+                connected_at: now,
+                last_heartbeat: now,
+                disconnected_at: None,
+                outbox: OutboxStats::default(),
+            },
+        );
+    }
+    pub fn hello(&mut self, id: usize, url: &str, ua: &str, features: &[String]) {
+        if let Some(client) = self.clients.get_mut(&id) {
+            let (browser, os) = user_agent::parse(ua);
+            client.user_agent = Some(ua.to_owned());
+            client.browser = browser;
+            client.os = os;
+            client.features = features.to_vec();
+            client.url = Some(url.to_owned());
+            client.page = page_path(url);
+        }
+    }
+    pub fn viewport(&mut self, id: usize, viewport: ViewportMsg) {
+        if let Some(client) = self.clients.get_mut(&id) {
+            client.viewport = Some(viewport);
+        }
+    }
+    pub fn join(&mut self, id: usize, room: &str) {
+        if let Some(client) = self.clients.get_mut(&id) {
+            client.room = room.to_owned();
+        }
+    }
+    pub fn heartbeat(&mut self, id: usize, now: u64) {
+        if let Some(client) = self.clients.get_mut(&id) {
+            client.last_heartbeat = now;
+        }
+    }
+    pub fn outbox(&mut self, id: usize, stats: OutboxStats) {
+        if let Some(client) = self.clients.get_mut(&id) {
+            client.outbox += stats;
+        }
+    }
+    pub fn disconnect(&mut self, id: usize, now: u64) {
+        if let Some(mut client) = self.clients.remove(&id) {
+            client.disconnected_at = Some(now);
+            self.departed.push_front(client);
+            self.departed.truncate(MAX_DEPARTED);
+        }
+    }
+    pub fn get(&self, id: usize) -> Option<&ClientInfo> {
+        self.clients.get(&id)
+    }
+    pub fn page(&self, id: usize) -> Option<&str> {
+        self.get(id).and_then(|client| client.page.as_deref())
+    }
+    pub fn list(&self) -> ClientList {
+        let mut connected: Vec<ClientInfo> = self.clients.values().cloned().collect();
+        connected.sort_by_key(|client| client.connected_at);
+        ClientList {
+            connected,
+            disconnected: self.departed.iter().cloned().collect(),
+        }
+    }
+}
+
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|dur| dur.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[test]
+fn test_registry() {
+    let mut registry = Registry::default();
+    registry.connect(1, "Main", 100);
+    registry.connect(2, "Main", 200);
+    registry.hello(
+        1,
+        "http://192.168.0.2:8090/about.html?a=b",
+        "Mozilla/5.0 (iPhone; CPU iPhone OS 15_2 like Mac OS X) Version/15.2 Mobile Safari/604.1",
+        &[String::from("ghost")],
+    );
+    registry.join(1, "/about.html");
+    registry.heartbeat(1, 300);
+
+    let client = registry.get(1).expect("connected");
+    assert_eq!(client.device_name(), "Safari 15 (iOS)");
+    assert_eq!(client.room, "/about.html");
+    assert_eq!(client.last_heartbeat, 300);
+    assert_eq!(registry.page(1), Some("/about.html"));
+
+    registry.disconnect(2, 400);
+    let list = registry.list();
+    assert_eq!(list.connected.len(), 1);
+    assert_eq!(list.disconnected.len(), 1);
+    assert_eq!(list.disconnected[0].disconnected_at, Some(400));
+    assert_eq!(list.disconnected[0].device_name(), "client 2");
+}
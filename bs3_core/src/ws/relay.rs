@@ -0,0 +1,303 @@
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::ws::client::{ClientMsg, PROTOCOL_VERSION};
+use crate::ws::server::{self, WsServer};
+use actix::io::SinkWrite;
+use actix::*;
+use actix_codec::Framed;
+use actix_rt::time::delay_for;
+use awc::error::WsProtocolError;
+use awc::ws::{Codec, Frame, Message};
+use awc::{BoxedSocket, Client};
+use bytes::Bytes;
+use futures::stream::{SplitSink, StreamExt};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use tokio::sync::oneshot;
+
+/// How often pings are sent to the other instance
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+/// How long to wait before reconnecting to an unreachable instance
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(2);
+/// How many relayed message ids are remembered for de-duplication
+const MAX_SEEN: usize = 1024;
+
+/// What a relay sends in its `Hello`, so the other instance treats it as a peer
+pub const RELAY_FEATURE: &str = "relay";
+
+///
+/// Another bs3 instance to share ghost-mode & reload messages with
+///
+/// eg: `--relay ws://192.168.0.5:8090` - the path defaults to the bs3 websocket
+///
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RelayTarget(pub url::Url);
+
+impl FromStr for RelayTarget {
+    type Err = RelayError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut url = url::Url::parse(s)?;
+        match url.scheme() {
+            "ws" | "wss" => {
+                if url.path() == "/" {
+                    url.set_path("/__bs3/ws/");
+                }
+                Ok(RelayTarget(url))
+            }
+            scheme => Err(RelayError::InvalidScheme(scheme.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for RelayTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Serialize for RelayTarget {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.0.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for RelayTarget {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        FromStr::from_str(&s).map_err(de::Error::custom)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RelayError {
+    #[error(
+        "invalid relay target: {0}
+
+    Valid examples:
+
+        bs3 --relay ws://192.168.0.5:8090
+        bs3 --relay wss://bs3.example.com
+
+    "
+    )]
+    InvalidTarget(#[from] url::ParseError),
+    #[error("invalid relay target scheme `{0}`, expected ws or wss")]
+    InvalidScheme(String),
+}
+
+///
+/// Ids of relayed messages that have already been handled. Instances may be
+/// connected in any shape, so the same message can arrive more than once
+///
+#[derive(Debug, Default)]
+pub struct RelaySeen {
+    order: VecDeque<u64>,
+    ids: HashSet<u64>,
+}
+
+impl RelaySeen {
+    /// `false` if this id was already seen
+    pub fn insert(&mut self, id: u64) -> bool {
+        if !self.ids.insert(id) {
+            return false;
+        }
+        self.order.push_back(id);
+        if self.order.len() > MAX_SEEN {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
+///
+/// Keep a relay connected to `target` for as long as bs3 is running
+///
+pub fn start(target: RelayTarget, token: Option<String>, server: Addr<WsServer>) {
+    actix_rt::spawn(async move {
+        loop {
+            match Client::new().ws(target.0.as_str()).connect().await {
+                Ok((_, framed)) => {
+                    log::info!("relaying to {}", target);
+                    let (done_tx, done_rx) = oneshot::channel();
+                    let (sink, stream) = framed.split();
+                    let server = server.clone();
+                    let token = token.clone();
+                    RelayPeer::create(|ctx| {
+                        RelayPeer::add_stream(stream, ctx);
+                        RelayPeer {
+                            id: 0,
+                            sink: SinkWrite::new(sink, ctx),
+                            server,
+                            token,
+                            done: Some(done_tx),
+                        }
+                    });
+                    let _ = done_rx.await;
+                    log::info!("relay to {} closed", target);
+                }
+                Err(e) => log::debug!("could not connect to relay {}: {}", target, e),
+            }
+            delay_for(RECONNECT_INTERVAL).await;
+        }
+    });
+}
+
+///
+/// The local end of a relay. To the local `WsServer` it looks like any other
+/// session, and to the remote instance it looks like a browser that said
+/// `Hello` with the `relay` feature
+///
+struct RelayPeer {
+    /// session id in the local WsServer
+    id: usize,
+    sink: SinkWrite<Message, SplitSink<Framed<BoxedSocket, Codec>, Message>>,
+    server: Addr<WsServer>,
+    /// `--relay-token`, which the other instance must also have been started with
+    token: Option<String>,
+    /// Lets `start` know when to reconnect
+    done: Option<oneshot::Sender<()>>,
+}
+
+impl Actor for RelayPeer {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.hb(ctx);
+        self.send(&ClientMsg::Hello {
+            url: String::from("bs3://relay"),
+            user_agent: String::from("bs3-relay"),
+            version: PROTOCOL_VERSION,
+            features: vec![RELAY_FEATURE.to_string()],
+            relay_token: self.token.clone(),
+        });
+
+        self.server
+            .send(server::Connect {
+                addr: ctx.address().recipient(),
+            })
+            .into_actor(self)
+            .then(|res, act, ctx| {
+                match res {
+                    Ok(id) => {
+                        act.id = id;
+                        // this end was started locally, so it's trusted without a token
+                        act.server.do_send(server::AddPeer { id });
+                    }
+                    _ => ctx.stop(),
+                }
+                fut::ready(())
+            })
+            .wait(ctx);
+    }
+
+    fn stopped(&mut self, _: &mut Self::Context) {
+        self.server.do_send(server::Disconnect { id: self.id });
+        if let Some(done) = self.done.take() {
+            let _ = done.send(());
+        }
+    }
+}
+
+/// Messages from the local WsServer, only `Relay` messages are meant for the other instance
+impl Handler<ClientMsg> for RelayPeer {
+    type Result = ();
+
+    fn handle(&mut self, msg: ClientMsg, _ctx: &mut Self::Context) {
+        if let ClientMsg::Relay(..) = msg {
+            self.send(&msg);
+        }
+    }
+}
+
+/// Messages from the other instance
+impl StreamHandler<Result<Frame, WsProtocolError>> for RelayPeer {
+    fn handle(&mut self, msg: Result<Frame, WsProtocolError>, ctx: &mut Self::Context) {
+        let text = match msg {
+            Ok(Frame::Text(text)) => text,
+            Ok(Frame::Ping(msg)) => {
+                let _ = self.sink.write(Message::Pong(msg));
+                return;
+            }
+            Ok(Frame::Close(_)) | Err(_) => {
+                ctx.stop();
+                return;
+            }
+            Ok(_) => return,
+        };
+        match serde_json::from_slice::<ClientMsg>(&text) {
+            Ok(ClientMsg::Relay(relay)) => self.server.do_send(server::ClientBroadcastMessage {
+                id: self.id,
+                msg: ClientMsg::Relay(relay),
+                room: String::from(RELAY_FEATURE),
+            }),
+            Ok(ClientMsg::Welcome(welcome)) => {
+                log::debug!("relay connected, remote protocol v{}", welcome.version)
+            }
+            Ok(ClientMsg::HardReload { reason }) => {
+                log::error!("the relay target runs an incompatible bs3: {}", reason);
+                ctx.stop();
+            }
+            Ok(_) => (),
+            Err(e) => log::error!("could not deserialize a relayed message: {}", e),
+        }
+    }
+
+    fn finished(&mut self, ctx: &mut Self::Context) {
+        ctx.stop();
+    }
+}
+
+impl actix::io::WriteHandler<WsProtocolError> for RelayPeer {}
+
+impl RelayPeer {
+    fn send(&mut self, msg: &ClientMsg) {
+        match serde_json::to_string(msg) {
+            Ok(json) => {
+                let _ = self.sink.write(Message::Text(json));
+            }
+            Err(e) => log::error!("could not serialize a relayed message: {}", e),
+        }
+    }
+
+    fn hb(&self, ctx: &mut Context<Self>) {
+        ctx.run_interval(HEARTBEAT_INTERVAL, |act, _ctx| {
+            let _ = act.sink.write(Message::Ping(Bytes::new()));
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relay_target() -> anyhow::Result<()> {
+        let target = RelayTarget::from_str("ws://192.168.0.5:8090")?;
+        assert_eq!(target.to_string(), "ws://192.168.0.5:8090/__bs3/ws/");
+        assert!(RelayTarget::from_str("http://192.168.0.5:8090").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_relay_seen() {
+        let mut seen = RelaySeen::default();
+        assert!(seen.insert(1));
+        assert!(!seen.insert(1));
+        for id in 2..(MAX_SEEN as u64 + 2) {
+            seen.insert(id);
+        }
+        // the oldest id has been forgotten
+        assert!(seen.insert(1));
+    }
+}
@@ -1,8 +1,28 @@
 use actix::prelude::*;
 
-use crate::ws::client::{ClientMsg, FsNotify};
+use crate::config::Config;
+use crate::reload_rules::ReloadRules;
+use crate::ws::client::{
+    ClientMsg, FsNotify, LogLevel, RelayMsg, ServedFile, WelcomeMsg, PROTOCOL_VERSION,
+};
+use crate::ws::console::format_log;
+use crate::ws::dep_graph::{web_path, DepGraph};
+use crate::ws::eval::{EvalError, EvalOutcome, Evals};
+use crate::ws::ghost::{EchoGuard, GhostMode};
+use crate::ws::hmr::ModuleGraph;
+use crate::ws::livereload::LrAlert;
+use crate::ws::outbox::Outbox;
+use crate::ws::recording::{Recorder, Recording, RecordingError};
+use crate::ws::registry::{now_ms, ClientList, Registry};
+use crate::ws::relay::{RelaySeen, RELAY_FEATURE};
 use rand::{self, rngs::ThreadRng, Rng};
 use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+
+/// How often queued messages are retried for sessions that couldn't keep up
+const OUTBOX_FLUSH_INTERVAL: Duration = Duration::from_millis(50);
 
 /// New chat session is created
 #[derive(Message)]
@@ -11,6 +31,31 @@ pub struct Connect {
     pub addr: Recipient<ClientMsg>,
 }
 
+/// A session introduced itself, place it into the room for its page.
+///
+/// The session receives `Welcome`, or `HardReload` when its protocol version
+/// doesn't match. Responds with the name of the joined room
+#[derive(Message, Debug)]
+#[rtype(result = "String")]
+pub struct Hello {
+    /// Client id
+    pub id: usize,
+    /// The full URL of the page the client is showing
+    pub url: String,
+    pub user_agent: String,
+    pub version: u32,
+    pub features: Vec<String>,
+    /// Must match `--relay-token` for a session with the `relay` feature to become a peer
+    pub relay_token: Option<String>,
+}
+
+/// A relay to another instance was started locally, treat its session as a peer
+#[derive(Message, Debug)]
+#[rtype(result = "()")]
+pub struct AddPeer {
+    pub id: usize,
+}
+
 /// Session is disconnected
 #[derive(Message)]
 #[rtype(result = "()")]
@@ -18,6 +63,57 @@ pub struct Disconnect {
     pub id: usize,
 }
 
+/// Session is still alive
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Heartbeat {
+    pub id: usize,
+}
+
+/// List every connected client, along with recently disconnected ones
+#[derive(Message)]
+#[rtype(result = "ClientList")]
+pub struct ListClients;
+
+/// Run JavaScript in the given clients, or every client when `clients` is empty.
+///
+/// Resolves once every browser has replied, or after `timeout`
+#[derive(Message)]
+#[rtype(result = "Result<Vec<EvalOutcome>, EvalError>")]
+pub struct Eval {
+    pub code: String,
+    pub clients: Vec<usize>,
+    pub timeout: Duration,
+}
+
+/// Start recording ghost-mode events, discarding any recording in progress
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct StartRecording;
+
+/// Stop recording and save it to `path`.
+///
+/// Responds with the number of recorded events
+#[derive(Message)]
+#[rtype(result = "Result<usize, RecordingError>")]
+pub struct StopRecording {
+    pub path: PathBuf,
+}
+
+/// Drive every connected browser through a saved recording, at the recorded pace.
+///
+/// Responds with the number of events that will be replayed
+#[derive(Message)]
+#[rtype(result = "Result<usize, RecordingError>")]
+pub struct Replay {
+    pub path: PathBuf,
+}
+
+/// Cancel a replay in progress
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct StopReplay;
+
 /// Send message to specific room
 #[derive(Message, Debug)]
 #[rtype(result = "()")]
@@ -30,6 +126,20 @@ pub struct ClientBroadcastMessage {
     pub room: String,
 }
 
+/// Send message to every session, in every room
+#[derive(Message, Debug)]
+#[rtype(result = "()")]
+pub struct SendAll {
+    pub msg: ClientMsg,
+}
+
+/// Also show error overlays in LiveReload clients, as an alert
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct RegisterLiveReload {
+    pub addr: Recipient<LrAlert>,
+}
+
 /// Join room, if room does not exists create new one.
 #[derive(Message)]
 #[rtype(result = "()")]
@@ -44,7 +154,29 @@ pub struct Join {
 /// session. implementation is super primitive
 pub struct WsServer {
     sessions: HashMap<usize, Recipient<ClientMsg>>,
+    /// Metadata about each session, eg: device, current page
+    clients: Registry,
     rooms: HashMap<String, HashSet<usize>>,
+    deps: DepGraph,
+    /// Only kept up to date with `--hmr`
+    modules: ModuleGraph,
+    hmr: bool,
+    reload_rules: ReloadRules,
+    ghost_mode: GhostMode,
+    echo_guard: EchoGuard,
+    outbox: Outbox,
+    /// Sessions that are other bs3 instances, connected with `--relay`
+    peers: HashSet<usize>,
+    relay_token: Option<String>,
+    relay_seen: RelaySeen,
+    client_logs: Vec<LogLevel>,
+    allow_eval: bool,
+    evals: Evals,
+    recorder: Option<Recorder>,
+    /// Pending events of a replay in progress, by their index in the recording
+    replay: HashMap<usize, SpawnHandle>,
+    /// Only set with `--livereload`
+    livereload: Option<Recipient<LrAlert>>,
     rng: ThreadRng,
 }
 
@@ -56,25 +188,323 @@ impl Default for WsServer {
 
         WsServer {
             sessions: HashMap::new(),
+            clients: Registry::default(),
             rooms,
+            deps: DepGraph::default(),
+            modules: ModuleGraph::default(),
+            hmr: false,
+            reload_rules: ReloadRules::default(),
+            ghost_mode: GhostMode::default(),
+            echo_guard: EchoGuard::default(),
+            outbox: Outbox::default(),
+            peers: HashSet::new(),
+            relay_token: None,
+            relay_seen: RelaySeen::default(),
+            client_logs: vec![],
+            allow_eval: false,
+            evals: Evals::default(),
+            recorder: None,
+            replay: HashMap::new(),
+            livereload: None,
             rng: rand::thread_rng(),
         }
     }
 }
 
 impl WsServer {
+    pub fn from_config(config: &Config) -> WsServer {
+        WsServer {
+            reload_rules: ReloadRules::new(&config.reload_rules),
+            ghost_mode: config.ghost_mode.clone(),
+            client_logs: config.client_logs.clone(),
+            allow_eval: config.allow_eval,
+            relay_token: config.relay_token.clone(),
+            hmr: config.hmr,
+            ..WsServer::default()
+        }
+    }
+
     /// Send message to all users in the room
-    fn send_message(&self, room: &str, message: ClientMsg, skip_id: usize) {
+    fn send_message(&mut self, room: &str, message: ClientMsg, skip_id: usize) {
+        let ids: Vec<usize> = match self.rooms.get(room) {
+            Some(sessions) => sessions
+                .iter()
+                .copied()
+                .filter(|id| *id != skip_id)
+                .collect(),
+            None => return,
+        };
+        for id in ids {
+            self.deliver(id, message.clone());
+        }
+    }
+
+    /// Send message to every session showing one of the given pages.
+    /// Sessions with an unknown page always receive it
+    fn send_to_pages(&mut self, message: ClientMsg, pages: Option<&HashSet<String>>) {
+        let ids: Vec<usize> = self.sessions.keys().copied().collect();
+        for id in ids {
+            let affected = match (pages, self.clients.page(id)) {
+                (Some(pages), Some(page)) => pages.contains(page),
+                _ => true,
+            };
+            if affected {
+                self.deliver(id, message.clone());
+            } else {
+                log::trace!("skipping session {}, not affected", id);
+            }
+        }
+    }
+
+    /// Send a message to a single session without letting its mailbox grow unbounded.
+    ///
+    /// Messages that can't be deferred (eg: reloads) always bypass the mailbox limit.
+    /// Others wait in the outbox when the session can't keep up, or if earlier ones
+    /// are already waiting, so that ordering is kept
+    fn deliver(&mut self, id: usize, message: ClientMsg) {
+        if self.peers.contains(&id) && !matches!(message, ClientMsg::Relay(..)) {
+            return;
+        }
+        if Outbox::is_deferrable(&message) && self.outbox.has_queued(id) {
+            self.queue(id, message);
+            return;
+        }
+        let addr = match self.sessions.get(&id) {
+            Some(addr) => addr,
+            None => return,
+        };
+        if !Outbox::is_deferrable(&message) {
+            let _ = addr.do_send(message);
+            return;
+        }
+        match addr.try_send(message) {
+            Ok(()) => (),
+            Err(SendError::Full(message)) => self.queue(id, message),
+            Err(SendError::Closed(_)) => log::trace!("session {} has closed", id),
+        }
+    }
+
+    fn queue(&mut self, id: usize, message: ClientMsg) {
+        let stats = self.outbox.push(id, message);
+        if !stats.is_empty() {
+            log::debug!("session {} is falling behind, {:?}", id, stats);
+            self.clients.outbox(id, stats);
+        }
+    }
+
+    /// Send a ghost-mode event to the rest of a room, remembering it so
+    /// that the same event coming back from those browsers isn't relayed again
+    fn send_ghost(&mut self, room: &str, msg: ClientMsg, skip_id: usize, now: Instant) {
+        self.send_message(room, msg.clone(), skip_id);
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.push(&msg, now);
+        }
         if let Some(sessions) = self.rooms.get(room) {
-            for id in sessions {
-                if *id != skip_id {
-                    if let Some(addr) = self.sessions.get(id) {
-                        let _ = addr.do_send(message.clone());
+            for other in sessions.iter().filter(|other| **other != skip_id) {
+                self.echo_guard.record(*other, &msg, now);
+            }
+        }
+    }
+
+    fn is_relay_token(&self, token: Option<&str>) -> bool {
+        match (self.relay_token.as_deref(), token) {
+            (Some(expected), Some(token)) => !expected.is_empty() && expected == token,
+            _ => false,
+        }
+    }
+
+    fn add_peer(&mut self, id: usize) {
+        log::info!("bs3 relay peer connected ({})", id);
+        self.peers.insert(id);
+        // peers aren't browsers, they don't belong to any page
+        for sessions in self.rooms.values_mut() {
+            sessions.remove(&id);
+        }
+    }
+
+    /// Share a message that originated here with every relay peer
+    fn relay_new(&mut self, room: Option<String>, msg: ClientMsg) {
+        if self.peers.is_empty() {
+            return;
+        }
+        let id = self.rng.gen::<u64>();
+        self.relay_seen.insert(id);
+        let relay = RelayMsg {
+            id,
+            room,
+            msg: Box::new(msg),
+        };
+        self.relay_out(relay, 0);
+    }
+
+    fn relay_out(&mut self, relay: RelayMsg, skip_id: usize) {
+        let peers: Vec<usize> = self
+            .peers
+            .iter()
+            .copied()
+            .filter(|id| *id != skip_id)
+            .collect();
+        for peer in peers {
+            self.deliver(peer, ClientMsg::Relay(relay.clone()));
+        }
+    }
+
+    /// A message from another bs3 instance - apply it here, then pass it on
+    /// to any other peers
+    fn relay_in(&mut self, from: usize, relay: RelayMsg) {
+        if !self.peers.contains(&from) {
+            log::warn!(
+                "ignoring a relay message from client {}, it's not a peer",
+                from
+            );
+            return;
+        }
+        if !self.relay_seen.insert(relay.id) {
+            log::trace!("already relayed {}", relay.id);
+            return;
+        }
+        let msg = (*relay.msg).clone();
+        match (&relay.room, self.ghost_mode.allows(&msg)) {
+            (_, Some(false)) => log::trace!("ghost mode disabled for relayed {:?}", msg),
+            (Some(room), Some(true)) => {
+                let room = room.clone();
+                self.send_ghost(&room, msg, from, Instant::now())
+            }
+            (None, None) if matches!(msg, ClientMsg::Reload { .. }) => {
+                self.send_to_pages(msg, None)
+            }
+            _ => log::debug!("ignoring relayed {:?}", msg),
+        }
+        self.relay_out(relay, from);
+    }
+
+    /// Retry queued messages, oldest first, until each mailbox is full again
+    fn flush_outbox(&mut self) {
+        for id in self.outbox.ids() {
+            while let Some(message) = self.outbox.pop(id) {
+                let sent = match self.sessions.get(&id) {
+                    Some(addr) => addr.try_send(message),
+                    None => {
+                        self.outbox.remove(id);
+                        break;
+                    }
+                };
+                match sent {
+                    Ok(()) => (),
+                    Err(SendError::Full(message)) => {
+                        self.outbox.unpop(id, message);
+                        break;
+                    }
+                    Err(SendError::Closed(_)) => {
+                        self.outbox.remove(id);
+                        break;
                     }
                 }
             }
         }
     }
+
+    /// Print a browser log in the terminal, if its level is enabled
+    fn print_log(
+        &self,
+        id: usize,
+        level: LogLevel,
+        args: &[String],
+        stack: Option<&str>,
+        url: Option<&str>,
+    ) {
+        if !self.client_logs.is_empty() && !self.client_logs.contains(&level) {
+            return;
+        }
+        let device = self.device_name(id);
+        println!("{}", format_log(&device, level, args, stack, url));
+    }
+
+    /// What this server has enabled, sent to each client in `Welcome`
+    fn features(&self) -> Vec<String> {
+        let ghost = &self.ghost_mode;
+        let mut features: Vec<String> = vec![
+            ("ghost.scroll", ghost.scroll),
+            ("ghost.clicks", ghost.clicks),
+            ("ghost.location", ghost.location),
+            ("ghost.inputs", ghost.forms.inputs),
+            ("ghost.submit", ghost.forms.submit),
+            ("ghost.toggles", ghost.forms.toggles),
+            ("eval", self.allow_eval),
+            ("hmr", self.hmr),
+        ]
+        .into_iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(name, _)| name.to_owned())
+        .collect();
+        features.push(String::from("console"));
+        features
+    }
+
+    /// (Re)parse the imports of a served ES module
+    fn parse_module(&mut self, module: &str, path: &Path) {
+        match std::fs::read_to_string(path) {
+            Ok(source) => self.modules.parse(module, &source),
+            Err(e) => log::debug!("could not read module {:?}: {}", path, e),
+        }
+    }
+
+    ///
+    /// With `--hmr`, a changed ES module that accepts hot updates (or whose importers
+    /// all accept it) is re-imported in place. Returns `false` if a reload is needed
+    ///
+    fn hmr_update(&mut self, item: &ServedFile, pages: Option<&HashSet<String>>) -> bool {
+        if !self.hmr || !ModuleGraph::is_module(&item.web_path) {
+            return false;
+        }
+        let module = web_path(&item.web_path);
+        self.parse_module(&module, &item.path);
+        if !self.modules.can_hot_update(&module) {
+            log::debug!("no hmr boundary for {}, reloading", module);
+            return false;
+        }
+        self.modules.replaced(&module);
+        self.send_to_pages(
+            ClientMsg::HmrUpdate {
+                modules: vec![module],
+                timestamp: now_ms(),
+            },
+            pages,
+        );
+        true
+    }
+
+    fn device_name(&self, id: usize) -> String {
+        self.clients
+            .get(id)
+            .map(|client| client.device_name())
+            .unwrap_or_else(|| format!("client {}", id))
+    }
+
+    /// Join room, send disconnect message to old room
+    /// send join message to new room
+    fn join(&mut self, id: usize, name: String) {
+        let mut rooms = Vec::new();
+
+        // remove session from all rooms
+        for (n, sessions) in &mut self.rooms {
+            if sessions.remove(&id) {
+                rooms.push(n.to_owned());
+            }
+        }
+        // send message to other users
+        for room in rooms {
+            self.send_message(&room, ClientMsg::Disconnect, 0);
+        }
+
+        self.rooms
+            .entry(name.clone())
+            .or_insert_with(HashSet::new)
+            .insert(id);
+        self.clients.join(id, &name);
+
+        self.send_message(&name, ClientMsg::Connect, id);
+    }
 }
 
 /// Make actor from `ChatServer`
@@ -83,6 +513,10 @@ impl Actor for WsServer {
     /// with other actors.
     type Context = Context<Self>;
 
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(OUTBOX_FLUSH_INTERVAL, |act, _| act.flush_outbox());
+    }
+
     fn stopped(&mut self, _ctx: &mut Self::Context) {
         log::debug!("stopped!");
     }
@@ -92,8 +526,43 @@ impl Handler<FsNotify> for WsServer {
     type Result = ();
 
     fn handle(&mut self, msg: FsNotify, _ctx: &mut Context<Self>) -> Self::Result {
-        let msg = ClientMsg::FsNotify(msg);
-        self.send_message(&"Main".to_owned(), msg, 0);
+        let client_msg = match self.reload_rules.client_msg(&msg.item) {
+            Some(client_msg) => client_msg,
+            None => {
+                log::debug!("ignoring change to {:?}", msg.item.web_path);
+                return;
+            }
+        };
+        let pages = self.deps.affected_pages(&msg.item.web_path);
+        log::debug!("pages affected by {:?} = {:?}", msg.item.web_path, pages);
+        if self.hmr_update(&msg.item, pages.as_ref()) {
+            return;
+        }
+        if let ClientMsg::Reload { .. } = client_msg {
+            self.relay_new(None, client_msg.clone());
+        }
+        self.send_to_pages(client_msg, pages.as_ref());
+    }
+}
+
+/// Record which page each served file belongs to
+impl Handler<bs3_files::served::ServedFile> for WsServer {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        msg: bs3_files::served::ServedFile,
+        _ctx: &mut Context<Self>,
+    ) -> Self::Result {
+        let served: ServedFile = msg.into();
+        if self.hmr && ModuleGraph::is_module(&served.web_path) {
+            self.modules.add_referer(&served);
+            let module = web_path(&served.web_path);
+            if !self.modules.is_parsed(&module) {
+                self.parse_module(&module, &served.path);
+            }
+        }
+        self.deps.add(&served);
     }
 }
 
@@ -111,6 +580,7 @@ impl Handler<Connect> for WsServer {
         let id = self.rng.gen::<usize>();
         log::trace!("+ client connected = ({})", id);
         self.sessions.insert(id, msg.addr);
+        self.clients.connect(id, "Main", now_ms());
 
         log::trace!("rooms before={:?}", self.rooms);
         // auto join session to Main room
@@ -134,6 +604,13 @@ impl Handler<Disconnect> for WsServer {
 
         let mut rooms: Vec<String> = Vec::new();
 
+        self.clients.disconnect(msg.id, now_ms());
+        self.echo_guard.remove(msg.id);
+        self.evals.disconnect(msg.id);
+        self.modules.disconnect(msg.id);
+        self.outbox.remove(msg.id);
+        self.peers.remove(&msg.id);
+
         // remove address
         if self.sessions.remove(&msg.id).is_some() {
             // remove session from all rooms
@@ -155,7 +632,76 @@ impl Handler<ClientBroadcastMessage> for WsServer {
     type Result = ();
 
     fn handle(&mut self, msg: ClientBroadcastMessage, _: &mut Context<Self>) {
-        self.send_message(&msg.room, msg.msg, msg.id);
+        let ClientBroadcastMessage { id, msg, room } = msg;
+        match self.ghost_mode.allows(&msg) {
+            Some(false) => log::trace!("ghost mode disabled for {:?}", msg),
+            Some(true) => {
+                let now = Instant::now();
+                if self.echo_guard.is_echo(id, &msg, now) {
+                    log::trace!("dropping echo from {} = {:?}", id, msg);
+                    return;
+                }
+                self.send_ghost(&room, msg.clone(), id, now);
+                self.relay_new(Some(room), msg);
+            }
+            None => match msg {
+                ClientMsg::Viewport(viewport) => self.clients.viewport(id, viewport),
+                ClientMsg::Log {
+                    level,
+                    args,
+                    stack,
+                    url,
+                } => self.print_log(id, level, &args, stack.as_deref(), url.as_deref()),
+                ClientMsg::EvalResult {
+                    id: eval_id,
+                    value,
+                    error,
+                } => self.evals.result(eval_id, id, value, error),
+                ClientMsg::Relay(relay) => self.relay_in(id, relay),
+                ClientMsg::HmrAccept { module, deps } => self.modules.accept(id, &module, &deps),
+                // handled by the session before it gets here
+                ClientMsg::Hello { .. } => log::trace!("late hello from client {}", id),
+                // everything else is only ever sent by the server, eg: reloads, overlays, evals
+                msg => log::warn!("ignoring {:?} sent by client {}", msg, id),
+            },
+        }
+    }
+}
+
+impl Handler<Heartbeat> for WsServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: Heartbeat, _: &mut Context<Self>) {
+        self.clients.heartbeat(msg.id, now_ms());
+    }
+}
+
+impl Handler<ListClients> for WsServer {
+    type Result = MessageResult<ListClients>;
+
+    fn handle(&mut self, _msg: ListClients, _: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.clients.list())
+    }
+}
+
+impl Handler<SendAll> for WsServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: SendAll, _: &mut Context<Self>) {
+        if let (ClientMsg::ErrorOverlay(overlay), Some(livereload)) = (&msg.msg, &self.livereload) {
+            let _ = livereload.do_send(LrAlert {
+                message: format!("{}\n\n{}", overlay.title, overlay.body),
+            });
+        }
+        self.send_to_pages(msg.msg, None);
+    }
+}
+
+impl Handler<RegisterLiveReload> for WsServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: RegisterLiveReload, _: &mut Context<Self>) {
+        self.livereload = Some(msg.addr);
     }
 }
 
@@ -166,24 +712,505 @@ impl Handler<Join> for WsServer {
 
     fn handle(&mut self, msg: Join, _: &mut Context<Self>) {
         let Join { id, name } = msg;
-        let mut rooms = Vec::new();
+        self.join(id, name);
+    }
+}
 
-        // remove session from all rooms
-        for (n, sessions) in &mut self.rooms {
-            if sessions.remove(&id) {
-                rooms.push(n.to_owned());
+impl Handler<Eval> for WsServer {
+    type Result = ResponseFuture<Result<Vec<EvalOutcome>, EvalError>>;
+
+    fn handle(&mut self, msg: Eval, ctx: &mut Context<Self>) -> Self::Result {
+        if !self.allow_eval {
+            return Box::pin(async { Err(EvalError::Disabled) });
+        }
+        let targets: HashMap<usize, String> = self
+            .sessions
+            .keys()
+            .filter(|id| !self.peers.contains(id))
+            .filter(|id| msg.clients.is_empty() || msg.clients.contains(id))
+            .map(|id| (*id, self.device_name(*id)))
+            .collect();
+        if targets.is_empty() {
+            return Box::pin(async { Err(EvalError::NoClients) });
+        }
+        let ids: Vec<usize> = targets.keys().copied().collect();
+        let (tx, rx) = oneshot::channel();
+        let eval_id = self.evals.start(targets, tx);
+        for id in ids {
+            if let Some(addr) = self.sessions.get(&id) {
+                let _ = addr.do_send(ClientMsg::Eval {
+                    id: eval_id,
+                    code: msg.code.clone(),
+                });
             }
         }
-        // send message to other users
-        for room in rooms {
-            self.send_message(&room, ClientMsg::Disconnect, 0);
+        ctx.run_later(msg.timeout, move |act, _| act.evals.timeout(eval_id));
+        Box::pin(async move { rx.await.map_err(|_| EvalError::Cancelled) })
+    }
+}
+
+impl Handler<StartRecording> for WsServer {
+    type Result = ();
+
+    fn handle(&mut self, _msg: StartRecording, _: &mut Context<Self>) {
+        log::debug!("recording started");
+        self.recorder = Some(Recorder::new(Instant::now()));
+    }
+}
+
+impl Handler<StopRecording> for WsServer {
+    type Result = Result<usize, RecordingError>;
+
+    fn handle(&mut self, msg: StopRecording, _: &mut Context<Self>) -> Self::Result {
+        let recording = self
+            .recorder
+            .take()
+            .ok_or(RecordingError::NotRecording)?
+            .finish();
+        recording.save(&msg.path)?;
+        log::debug!("saved {} events to {:?}", recording.events.len(), msg.path);
+        Ok(recording.events.len())
+    }
+}
+
+impl Handler<Replay> for WsServer {
+    type Result = Result<usize, RecordingError>;
+
+    fn handle(&mut self, msg: Replay, ctx: &mut Context<Self>) -> Self::Result {
+        let recording = Recording::load(&msg.path)?;
+        // anything else (eg: evals or reloads) could bypass what this server allows
+        let mut events = vec![];
+        for (index, (at, event)) in recording.schedule().enumerate() {
+            match self.ghost_mode.allows(event) {
+                Some(true) => events.push((index, at, event.clone())),
+                Some(false) => log::debug!("ghost mode disabled, not replaying {:?}", event),
+                None => return Err(RecordingError::NotGhostMode(index)),
+            }
         }
+        for (_, handle) in self.replay.drain() {
+            ctx.cancel_future(handle);
+        }
+        let count = events.len();
+        for (index, at, event) in events {
+            let handle = ctx.run_later(at, move |act, _| {
+                act.replay.remove(&index);
+                // browsers will emit these same events while applying them
+                let now = Instant::now();
+                for id in act.sessions.keys() {
+                    act.echo_guard.record(*id, &event, now);
+                }
+                act.send_to_pages(event, None);
+            });
+            self.replay.insert(index, handle);
+        }
+        Ok(count)
+    }
+}
 
-        self.rooms
-            .entry(name.clone())
-            .or_insert_with(HashSet::new)
-            .insert(id);
+impl Handler<StopReplay> for WsServer {
+    type Result = ();
 
-        self.send_message(&name, ClientMsg::Connect, id);
+    fn handle(&mut self, _msg: StopReplay, ctx: &mut Context<Self>) {
+        for (_, handle) in self.replay.drain() {
+            ctx.cancel_future(handle);
+        }
+    }
+}
+
+impl Handler<AddPeer> for WsServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: AddPeer, _: &mut Context<Self>) {
+        self.add_peer(msg.id);
+    }
+}
+
+impl Handler<Hello> for WsServer {
+    type Result = String;
+
+    fn handle(&mut self, msg: Hello, _: &mut Context<Self>) -> Self::Result {
+        let Hello {
+            id,
+            url,
+            user_agent,
+            version,
+            features,
+            relay_token,
+        } = msg;
+        log::debug!("hello from {} = {} ({}) v{}", id, url, user_agent, version);
+        let reply = if version == PROTOCOL_VERSION {
+            ClientMsg::Welcome(WelcomeMsg {
+                version: PROTOCOL_VERSION,
+                features: self.features(),
+                reload_rules: self.reload_rules.summary(),
+            })
+        } else {
+            log::debug!(
+                "client {} is outdated, v{} != v{}",
+                id,
+                version,
+                PROTOCOL_VERSION
+            );
+            ClientMsg::HardReload {
+                reason: format!(
+                    "client protocol v{} does not match server v{}",
+                    version, PROTOCOL_VERSION
+                ),
+            }
+        };
+        if let Some(addr) = self.sessions.get(&id) {
+            let _ = addr.do_send(reply);
+        }
+        self.clients.hello(id, &url, &user_agent, &features);
+        if features.iter().any(|feature| feature == RELAY_FEATURE) {
+            if self.is_relay_token(relay_token.as_deref()) {
+                self.add_peer(id);
+                return String::from(RELAY_FEATURE);
+            }
+            log::warn!(
+                "client {} asked to be a relay peer without a valid --relay-token",
+                id
+            );
+        }
+        let room = room_for(&url);
+        self.join(id, room.clone());
+        room
+    }
+}
+
+///
+/// Sessions are grouped by the page they are showing, unless an explicit
+/// group is given with the `bs3-room` query param
+///
+/// eg: `http://localhost:8090/about.html?bs3-room=checkout` -> `checkout`
+///
+pub fn room_for(url: &str) -> String {
+    match url::Url::parse(url) {
+        Ok(url) => url
+            .query_pairs()
+            .find(|(key, _)| key == "bs3-room")
+            .map(|(_, room)| room.to_string())
+            .unwrap_or_else(|| url.path().to_string()),
+        Err(_) => "Main".to_owned(),
+    }
+}
+
+#[test]
+fn test_room_for() {
+    assert_eq!(
+        room_for("http://localhost:8090/about.html?a=b"),
+        "/about.html"
+    );
+    assert_eq!(
+        room_for("http://192.168.0.2:8090/about.html?bs3-room=checkout"),
+        "checkout"
+    );
+    assert_eq!(room_for("not a url"), "Main");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::Collector;
+    use crate::ws::client::{ClickMsg, ErrorOverlayMsg, ScrollMsg};
+    use crate::ws::recording::RecordedEvent;
+    use std::sync::{Arc, Mutex};
+
+    async fn hello(
+        srv: &Addr<WsServer>,
+        id: usize,
+        url: &str,
+        features: &[&str],
+        relay_token: Option<&str>,
+    ) -> String {
+        srv.send(Hello {
+            id,
+            url: url.to_owned(),
+            user_agent: String::from("test"),
+            version: PROTOCOL_VERSION,
+            features: features.iter().map(|f| f.to_string()).collect(),
+            relay_token: relay_token.map(String::from),
+        })
+        .await
+        .expect("hello")
+    }
+
+    /// Let messages sent by the server reach each collector
+    async fn settle() {
+        actix_rt::time::delay_for(Duration::from_millis(50)).await;
+    }
+
+    fn ghost_events(collector: &Collector) -> Vec<ClientMsg> {
+        collector
+            .received()
+            .into_iter()
+            .filter(|msg| matches!(msg, ClientMsg::Scroll(..) | ClientMsg::Click(..)))
+            .collect()
+    }
+
+    fn relayed(collector: &Collector) -> Vec<RelayMsg> {
+        collector
+            .received()
+            .into_iter()
+            .filter_map(|msg| match msg {
+                ClientMsg::Relay(relay) => Some(relay),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn relay_scroll(id: u64) -> ClientMsg {
+        ClientMsg::Relay(RelayMsg {
+            id,
+            room: Some(String::from("/page")),
+            msg: Box::new(ClientMsg::Scroll(ScrollMsg { x: 0.0, y: 0.5 })),
+        })
+    }
+
+    fn with_relay_token() -> WsServer {
+        WsServer {
+            relay_token: Some(String::from("s3cret")),
+            ..WsServer::default()
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_relay_peers_need_the_token() {
+        let srv = with_relay_token().start();
+        let (browser, browser_rx) = Collector::connect(&srv).await;
+        hello(&srv, browser, "http://localhost:8090/page", &[], None).await;
+
+        // eg: any page on the LAN
+        let (intruder, _) = Collector::connect(&srv).await;
+        let room = hello(&srv, intruder, "bs3://relay", &[RELAY_FEATURE], None).await;
+        assert_ne!(room, RELAY_FEATURE);
+        srv.do_send(ClientBroadcastMessage {
+            id: intruder,
+            msg: relay_scroll(1),
+            room,
+        });
+
+        let (wrong, _) = Collector::connect(&srv).await;
+        let room = hello(&srv, wrong, "bs3://relay", &[RELAY_FEATURE], Some("guess")).await;
+        assert_ne!(room, RELAY_FEATURE);
+
+        let (peer, _) = Collector::connect(&srv).await;
+        let room = hello(&srv, peer, "bs3://relay", &[RELAY_FEATURE], Some("s3cret")).await;
+        assert_eq!(room, RELAY_FEATURE);
+        srv.do_send(ClientBroadcastMessage {
+            id: peer,
+            msg: relay_scroll(2),
+            room,
+        });
+
+        settle().await;
+        assert_eq!(ghost_events(&browser_rx).len(), 1);
+
+        // without a token of its own, nothing is accepted
+        let srv = WsServer::default().start();
+        let (peer, _) = Collector::connect(&srv).await;
+        let room = hello(&srv, peer, "bs3://relay", &[RELAY_FEATURE], Some("")).await;
+        assert_ne!(room, RELAY_FEATURE);
+    }
+
+    #[actix_rt::test]
+    async fn test_relay_routing() {
+        let srv = with_relay_token().start();
+        let (browser, browser_rx) = Collector::connect(&srv).await;
+        hello(&srv, browser, "http://localhost:8090/page", &[], None).await;
+        let (peer_a, peer_a_rx) = Collector::connect(&srv).await;
+        srv.do_send(AddPeer { id: peer_a });
+        let (peer_b, peer_b_rx) = Collector::connect(&srv).await;
+        hello(
+            &srv,
+            peer_b,
+            "bs3://relay",
+            &[RELAY_FEATURE],
+            Some("s3cret"),
+        )
+        .await;
+
+        // relay_in: applied to the page's room, then passed on to the other peer only
+        srv.do_send(ClientBroadcastMessage {
+            id: peer_a,
+            msg: relay_scroll(7),
+            room: String::from(RELAY_FEATURE),
+        });
+        settle().await;
+        assert_eq!(ghost_events(&browser_rx).len(), 1);
+        assert!(relayed(&peer_a_rx).is_empty());
+        assert_eq!(relayed(&peer_b_rx).len(), 1);
+        assert_eq!(relayed(&peer_b_rx)[0].id, 7);
+
+        // the same message coming back around a loop is dropped
+        srv.do_send(ClientBroadcastMessage {
+            id: peer_b,
+            msg: relay_scroll(7),
+            room: String::from(RELAY_FEATURE),
+        });
+        settle().await;
+        assert_eq!(ghost_events(&browser_rx).len(), 1);
+        assert!(relayed(&peer_a_rx).is_empty());
+
+        // relay_out: events from local browsers go to every peer, but never to browsers
+        let click = ClientMsg::Click(ClickMsg {
+            selector: String::from("#submit"),
+        });
+        srv.do_send(ClientBroadcastMessage {
+            id: browser,
+            msg: click.clone(),
+            room: String::from("/page"),
+        });
+        settle().await;
+        for rx in &[&peer_a_rx, &peer_b_rx] {
+            let out = relayed(rx);
+            let last = out.last().expect("relayed");
+            assert_eq!(*last.msg, click);
+            assert_eq!(last.room.as_deref(), Some("/page"));
+        }
+        assert!(relayed(&browser_rx).is_empty());
+        assert!(ghost_events(&peer_a_rx).is_empty());
+    }
+
+    fn save(name: &str, events: Vec<ClientMsg>) -> PathBuf {
+        let recording = Recording {
+            events: events
+                .into_iter()
+                .map(|msg| RecordedEvent { at_ms: 0, msg })
+                .collect(),
+        };
+        let path = std::env::temp_dir().join(format!("bs3-{}-{}.json", name, std::process::id()));
+        recording.save(&path).expect("saved");
+        path
+    }
+
+    #[actix_rt::test]
+    async fn test_replay_only_ghost_mode() -> anyhow::Result<()> {
+        let srv = WsServer::default().start();
+        let (_, collector) = Collector::connect(&srv).await;
+
+        let scroll = ClientMsg::Scroll(ScrollMsg { x: 0.0, y: 0.5 });
+        let unsafe_path = save(
+            "replay-eval",
+            vec![
+                scroll.clone(),
+                ClientMsg::Eval {
+                    id: 1,
+                    code: String::from("document.cookie"),
+                },
+            ],
+        );
+        let res = srv.send(Replay { path: unsafe_path }).await?;
+        assert!(matches!(res, Err(RecordingError::NotGhostMode(1))));
+
+        let path = save(
+            "replay-ghost",
+            vec![
+                scroll.clone(),
+                ClientMsg::Click(ClickMsg {
+                    selector: String::from("#submit"),
+                }),
+            ],
+        );
+        assert_eq!(srv.send(Replay { path }).await??, 2);
+        actix_rt::time::delay_for(Duration::from_millis(50)).await;
+
+        let received: Vec<ClientMsg> = collector
+            .received()
+            .into_iter()
+            .filter(|msg| !matches!(msg, ClientMsg::Connect))
+            .collect();
+        assert_eq!(received.len(), 2);
+        assert_eq!(received[0], scroll);
+        Ok(())
+    }
+
+    /// Stands in for `LiveReloadServer`
+    #[derive(Default, Clone)]
+    struct Alerts(Arc<Mutex<Vec<String>>>);
+
+    impl Actor for Alerts {
+        type Context = Context<Self>;
+    }
+
+    impl Handler<LrAlert> for Alerts {
+        type Result = ();
+
+        fn handle(&mut self, msg: LrAlert, _: &mut Context<Self>) {
+            self.0.lock().expect("lock").push(msg.message);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_overlays_alert_livereload() {
+        let srv = WsServer::default().start();
+        let alerts = Alerts::default();
+        srv.do_send(RegisterLiveReload {
+            addr: alerts.clone().start().recipient(),
+        });
+        srv.do_send(SendAll {
+            msg: ClientMsg::Notify {
+                message: String::from("not an error"),
+                timeout: None,
+            },
+        });
+        srv.do_send(SendAll {
+            msg: ClientMsg::ErrorOverlay(ErrorOverlayMsg {
+                title: String::from("Proxy error"),
+                body: String::from("Could not reach http://localhost:3000/"),
+                file: None,
+                line: None,
+            }),
+        });
+        settle().await;
+        assert_eq!(
+            *alerts.0.lock().expect("lock"),
+            vec![String::from(
+                "Proxy error\n\nCould not reach http://localhost:3000/"
+            )]
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_browsers_cant_send_server_messages() {
+        let srv = WsServer::default().start();
+        let (sender, _) = Collector::connect(&srv).await;
+        let room = hello(&srv, sender, "http://localhost:8090/page", &[], None).await;
+        let (other_id, other) = Collector::connect(&srv).await;
+        hello(&srv, other_id, "http://localhost:8090/page", &[], None).await;
+        let forged = vec![
+            ClientMsg::ErrorOverlay(ErrorOverlayMsg {
+                title: String::from("Forged"),
+                body: String::from("from another device"),
+                file: None,
+                line: None,
+            }),
+            ClientMsg::Reload {
+                reason: String::from("forged"),
+            },
+            ClientMsg::Notify {
+                message: String::from("forged"),
+                timeout: None,
+            },
+        ];
+        for msg in forged.iter().cloned() {
+            srv.do_send(ClientBroadcastMessage {
+                id: sender,
+                msg,
+                room: room.clone(),
+            });
+        }
+        // ghost-mode events still get through
+        let scroll = ClientMsg::Scroll(ScrollMsg { x: 0.0, y: 0.5 });
+        srv.do_send(ClientBroadcastMessage {
+            id: sender,
+            msg: scroll.clone(),
+            room: room.clone(),
+        });
+        settle().await;
+
+        let received = other.received();
+        assert!(forged.iter().all(|msg| !received.contains(msg)));
+        assert!(received.contains(&scroll));
     }
 }
@@ -0,0 +1,87 @@
+use crate::ws::client::{ClientMsg, PROTOCOL_VERSION};
+use crate::ws::server::{self, WsServer};
+use actix::Addr;
+
+///
+/// The transport-agnostic part of a browser session, shared by
+/// the WebSocket and Server-Sent Events sessions
+///
+pub struct SessionState {
+    /// unique session id, assigned by `WsServer` on `Connect`
+    pub id: usize,
+    /// joined room
+    pub room: String,
+    /// protocol version sent by the client in its `Hello`
+    pub version: Option<u32>,
+    /// Ws server
+    pub addr: Addr<WsServer>,
+}
+
+///
+/// What a session should do with a message from its browser
+///
+pub enum Incoming {
+    /// Already passed on to `WsServer`, nothing left to do
+    Handled,
+    /// The client introduced itself, send this to `WsServer` and join the room it replies with
+    Hello(server::Hello),
+    /// Send this straight back to the browser
+    Reply(ClientMsg),
+}
+
+impl SessionState {
+    pub fn new(addr: Addr<WsServer>) -> Self {
+        Self {
+            id: 0,
+            room: "Main".to_owned(),
+            version: None,
+            addr,
+        }
+    }
+
+    pub fn incoming(&mut self, text: &str) -> Incoming {
+        log::trace!(">>> incoming text from {} = {}", self.id, text);
+
+        match serde_json::from_str::<ClientMsg>(text) {
+            Ok(ClientMsg::Hello {
+                url,
+                user_agent,
+                version,
+                features,
+                relay_token,
+            }) => {
+                self.version = Some(version);
+                Incoming::Hello(server::Hello {
+                    id: self.id,
+                    url,
+                    user_agent,
+                    version,
+                    features,
+                    relay_token,
+                })
+            }
+            Ok(msg) => {
+                log::trace!("✔ deserialized a clientMsg {:?}", msg);
+                self.addr.do_send(server::ClientBroadcastMessage {
+                    id: self.id,
+                    msg,
+                    room: self.room.clone(),
+                });
+                Incoming::Handled
+            }
+            Err(e) => {
+                log::error!("could not deserialize an incoming message:");
+                log::error!("{:?}", e);
+                // a client that never completed a handshake is most likely
+                // a stale, cached bundle - get it to fetch a fresh one
+                if self.version != Some(PROTOCOL_VERSION) {
+                    Incoming::Reply(ClientMsg::HardReload {
+                        reason: String::from("unrecognised message from an outdated client"),
+                    })
+                } else {
+                    Incoming::Handled
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,325 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::ws::client::ClientMsg;
+use crate::ws::outbox::Outbox;
+use crate::ws::server;
+use crate::ws::session::{Incoming, SessionState};
+use actix::*;
+use actix_web::error::ErrorNotFound;
+use actix_web::http::header::CACHE_CONTROL;
+use actix_web::web::{self, Bytes};
+use actix_web::{Error, HttpResponse};
+use futures::channel::mpsc;
+
+/// How often a comment is sent to keep proxies from closing an idle stream
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How many events may wait for a browser that's reading the stream slowly
+const MAX_BUFFERED: usize = 64;
+
+///
+/// Every live SSE session, so that `POST /__bs3/msg` can find the one
+/// a message belongs to
+///
+#[derive(Default)]
+pub struct SseSessions(Mutex<HashMap<usize, Addr<SseSession>>>);
+
+impl SseSessions {
+    fn insert(&self, id: usize, addr: Addr<SseSession>) {
+        self.0.lock().expect("sse sessions lock").insert(id, addr);
+    }
+    fn remove(&self, id: usize) {
+        self.0.lock().expect("sse sessions lock").remove(&id);
+    }
+    fn get(&self, id: usize) -> Option<Addr<SseSession>> {
+        self.0.lock().expect("sse sessions lock").get(&id).cloned()
+    }
+}
+
+///
+/// Fallback for networks that block WebSocket upgrades. Server -> client messages
+/// are streamed as `text/event-stream` from here, client -> server messages
+/// arrive via [`msg_route`]
+///
+pub async fn sse_route(
+    srv: web::Data<Addr<server::WsServer>>,
+    sessions: web::Data<SseSessions>,
+) -> HttpResponse {
+    let (tx, rx) = mpsc::channel(MAX_BUFFERED);
+    SseSession {
+        state: SessionState::new(srv.get_ref().clone()),
+        tx,
+        sessions,
+    }
+    .start();
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .header(CACHE_CONTROL, "no-cache")
+        .streaming(rx)
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct MsgQuery {
+    /// The session id, as sent in the `session` event
+    pub id: usize,
+}
+
+/// A single `ClientMsg`, as JSON, from a browser using the SSE transport
+pub async fn msg_route(
+    query: web::Query<MsgQuery>,
+    body: String,
+    sessions: web::Data<SseSessions>,
+) -> Result<HttpResponse, Error> {
+    let addr = sessions
+        .get(query.id)
+        .ok_or_else(|| ErrorNotFound("unknown sse session"))?;
+    addr.do_send(IncomingText { text: body });
+    Ok(HttpResponse::Accepted().finish())
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+struct IncomingText {
+    text: String,
+}
+
+pub struct SseSession {
+    state: SessionState,
+    tx: mpsc::Sender<Result<Bytes, Error>>,
+    sessions: web::Data<SseSessions>,
+}
+
+impl Actor for SseSession {
+    type Context = Context<Self>;
+
+    /// Register with WsServer in exactly the same way as `WsSession`,
+    /// then tell the browser its id so that it can send messages back
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.hb(ctx);
+
+        let addr = ctx.address();
+        self.state
+            .addr
+            .send(server::Connect {
+                addr: addr.clone().recipient(),
+            })
+            .into_actor(self)
+            .then(move |res, act, ctx| {
+                match res {
+                    Ok(id) => {
+                        act.state.id = id;
+                        act.sessions.insert(id, addr);
+                        act.send(format!("event: session\ndata: {}\n\n", id), ctx);
+                    }
+                    _ => ctx.stop(),
+                }
+                fut::ready(())
+            })
+            .wait(ctx);
+    }
+
+    fn stopping(&mut self, _: &mut Self::Context) -> Running {
+        self.sessions.remove(self.state.id);
+        self.state
+            .addr
+            .do_send(server::Disconnect { id: self.state.id });
+        Running::Stop
+    }
+}
+
+/// Send messages to clients (browsers)
+/// These are the OUTGOING messages
+impl Handler<ClientMsg> for SseSession {
+    type Result = ();
+
+    fn handle(&mut self, msg: ClientMsg, ctx: &mut Self::Context) {
+        match msg {
+            // we don't forward messages such as connect/disconnect
+            ClientMsg::Connect | ClientMsg::Disconnect => (),
+            msg => match serde_json::to_string(&msg) {
+                Ok(json) => {
+                    log::trace!("~~> sending sse {} = {}", self.state.id, json);
+                    if self.send(format!("data: {}\n\n", json), ctx) {
+                        return;
+                    }
+                    // the same messages `WsServer` would drop for a slow websocket
+                    if Outbox::is_deferrable(&msg) {
+                        log::trace!(
+                            "sse session {} is falling behind, dropped {:?}",
+                            self.state.id,
+                            msg
+                        );
+                    } else {
+                        // it's missed something important, reconnecting brings it back in sync
+                        log::debug!("sse session {} can't keep up, closing it", self.state.id);
+                        ctx.stop();
+                    }
+                }
+                Err(_) => log::error!("not a json message"),
+            },
+        }
+    }
+}
+
+impl Handler<IncomingText> for SseSession {
+    type Result = ();
+
+    fn handle(&mut self, msg: IncomingText, ctx: &mut Self::Context) {
+        match self.state.incoming(&msg.text) {
+            Incoming::Handled => (),
+            Incoming::Hello(hello) => self.hello(hello, ctx),
+            Incoming::Reply(msg) => ctx.notify(msg),
+        }
+    }
+}
+
+impl SseSession {
+    /// Write a raw event to the stream, stopping once the browser has gone.
+    /// Returns false if it wasn't written
+    fn send(&mut self, event: String, ctx: &mut Context<Self>) -> bool {
+        match self.tx.try_send(Ok(Bytes::from(event))) {
+            Ok(()) => true,
+            Err(e) => {
+                if e.is_disconnected() {
+                    ctx.stop();
+                }
+                false
+            }
+        }
+    }
+
+    /// The client told us which page it's on - move it into the matching room
+    fn hello(&self, hello: server::Hello, ctx: &mut Context<Self>) {
+        self.state
+            .addr
+            .send(hello)
+            .into_actor(self)
+            .then(|res, act, _ctx| {
+                match res {
+                    Ok(room) => {
+                        log::trace!("{} joined room {}", act.state.id, room);
+                        act.state.room = room
+                    }
+                    Err(e) => log::error!("could not join a room {}", e),
+                }
+                fut::ready(())
+            })
+            .wait(ctx);
+    }
+
+    /// There's no ping/pong in SSE, so a failed write is the only way to
+    /// notice a browser has gone
+    fn hb(&self, ctx: &mut Context<Self>) {
+        ctx.run_interval(HEARTBEAT_INTERVAL, |act, ctx| {
+            act.send(String::from(": ping\n\n"), ctx);
+            act.state
+                .addr
+                .do_send(server::Heartbeat { id: act.state.id });
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ws::client::PROTOCOL_VERSION;
+    use crate::ws::server::{SendAll, WsServer};
+    use actix_web::dev::{Body, ResponseBody};
+    use actix_web::http::header::CONTENT_TYPE;
+    use actix_web::test::{self, TestRequest};
+    use actix_web::App;
+    use futures::StreamExt;
+
+    /// The next event from the stream, skipping heartbeats
+    async fn next_event(body: &mut ResponseBody<Body>) -> String {
+        loop {
+            let chunk = actix_rt::time::timeout(Duration::from_secs(1), body.next())
+                .await
+                .expect("an event in time")
+                .expect("the stream is open")
+                .expect("a chunk");
+            let event = String::from_utf8(chunk.to_vec()).expect("utf8");
+            if !event.starts_with(':') {
+                return event;
+            }
+        }
+    }
+
+    fn session_id(event: &str) -> usize {
+        event
+            .trim()
+            .strip_prefix("event: session\ndata: ")
+            .expect("the session event comes first")
+            .parse()
+            .expect("a numeric id")
+    }
+
+    fn msg(id: usize, text: impl Into<String>) -> TestRequest {
+        TestRequest::post()
+            .uri(&format!("/__bs3/msg?id={}", id))
+            .set_payload(text.into())
+    }
+
+    #[actix_rt::test]
+    async fn test_sse_session() -> anyhow::Result<()> {
+        let srv = WsServer::default().start();
+        let mut app = test::init_service(
+            App::new()
+                .data(srv.clone())
+                .app_data(web::Data::new(SseSessions::default()))
+                .service(web::resource("/__bs3/sse").to(sse_route))
+                .service(web::resource("/__bs3/msg").route(web::post().to(msg_route))),
+        )
+        .await;
+        let hello = serde_json::to_string(&ClientMsg::Hello {
+            url: String::from("http://localhost:8090/"),
+            user_agent: String::from("test"),
+            version: PROTOCOL_VERSION,
+            features: vec![],
+            relay_token: None,
+        })?;
+
+        let req = TestRequest::with_uri("/__bs3/sse").to_request();
+        let mut res = test::call_service(&mut app, req).await;
+        assert_eq!(
+            res.headers().get(CONTENT_TYPE).expect("set"),
+            "text/event-stream"
+        );
+        let mut body = res.take_body();
+        let id = session_id(&next_event(&mut body).await);
+
+        // hello
+        let res = test::call_service(&mut app, msg(id, hello.clone()).to_request()).await;
+        assert_eq!(res.status(), 202);
+        assert!(next_event(&mut body).await.contains(r#""kind":"Welcome""#));
+
+        // broadcasts
+        srv.do_send(SendAll {
+            msg: ClientMsg::Notify {
+                message: String::from("hi"),
+                timeout: None,
+            },
+        });
+        let event = next_event(&mut body).await;
+        assert!(event.starts_with("data: "));
+        assert!(event.contains(r#""message":"hi""#));
+
+        // bad input from a session that never said hello, most likely an outdated bundle
+        let req = TestRequest::with_uri("/__bs3/sse").to_request();
+        let mut other = test::call_service(&mut app, req).await.take_body();
+        let other_id = session_id(&next_event(&mut other).await);
+        let res = test::call_service(&mut app, msg(other_id, "not a message").to_request()).await;
+        assert_eq!(res.status(), 202);
+        assert!(next_event(&mut other)
+            .await
+            .contains(r#""kind":"HardReload""#));
+
+        // unknown sessions
+        let unknown = id.max(other_id) + 1;
+        let res = test::call_service(&mut app, msg(unknown, hello).to_request()).await;
+        assert_eq!(res.status(), 404);
+        Ok(())
+    }
+}
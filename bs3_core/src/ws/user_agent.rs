@@ -0,0 +1,80 @@
+///
+/// Very small user-agent sniffing, just enough to tell devices apart in the terminal
+///
+/// eg: `Chrome 96`, `Android`
+///
+pub fn parse(ua: &str) -> (Option<String>, Option<String>) {
+    (browser(ua), os(ua))
+}
+
+fn browser(ua: &str) -> Option<String> {
+    const BROWSERS: &[(&str, &str)] = &[
+        ("Edg/", "Edge"),
+        ("OPR/", "Opera"),
+        ("SamsungBrowser/", "Samsung Internet"),
+        ("FxiOS/", "Firefox"),
+        ("Firefox/", "Firefox"),
+        ("CriOS/", "Chrome"),
+        ("Chrome/", "Chrome"),
+        ("Version/", "Safari"),
+    ];
+    BROWSERS
+        .iter()
+        .find(|(token, _)| ua.contains(token))
+        .map(|(token, name)| match major_version(ua, token) {
+            Some(version) => format!("{} {}", name, version),
+            None => name.to_string(),
+        })
+}
+
+fn os(ua: &str) -> Option<String> {
+    const OPERATING_SYSTEMS: &[(&str, &str)] = &[
+        ("iPhone", "iOS"),
+        ("iPad", "iPadOS"),
+        ("Android", "Android"),
+        ("Windows", "Windows"),
+        ("CrOS", "ChromeOS"),
+        ("Mac OS X", "macOS"),
+        ("Linux", "Linux"),
+    ];
+    OPERATING_SYSTEMS
+        .iter()
+        .find(|(token, _)| ua.contains(token))
+        .map(|(_, name)| name.to_string())
+}
+
+fn major_version(ua: &str, token: &str) -> Option<String> {
+    let start = ua.find(token)? + token.len();
+    let version: String = ua[start..]
+        .chars()
+        .take_while(|c| c.is_ascii_digit())
+        .collect();
+    if version.is_empty() {
+        None
+    } else {
+        Some(version)
+    }
+}
+
+#[test]
+fn test_parse() {
+    let chrome_android = "Mozilla/5.0 (Linux; Android 11; Pixel 5) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/96.0.4664.104 Mobile Safari/537.36";
+    assert_eq!(
+        parse(chrome_android),
+        (
+            Some(String::from("Chrome 96")),
+            Some(String::from("Android"))
+        )
+    );
+    let safari_ios = "Mozilla/5.0 (iPhone; CPU iPhone OS 15_2 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/15.2 Mobile/15E148 Safari/604.1";
+    assert_eq!(
+        parse(safari_ios),
+        (Some(String::from("Safari 15")), Some(String::from("iOS")))
+    );
+    let edge = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/96.0.4664.110 Safari/537.36 Edg/96.0.1054.62";
+    assert_eq!(
+        parse(edge),
+        (Some(String::from("Edge 96")), Some(String::from("Windows")))
+    );
+    assert_eq!(parse("curl/7.64.1"), (None, None));
+}
@@ -2,6 +2,7 @@ use std::time::{Duration, Instant};
 
 use crate::ws::client::ClientMsg;
 use crate::ws::server;
+use crate::ws::session::{Incoming, SessionState};
 use actix::*;
 use actix_web::{web, Error, HttpRequest, HttpResponse};
 use actix_web_actors::ws;
@@ -19,10 +20,8 @@ pub async fn ws_route(
 ) -> Result<HttpResponse, Error> {
     ws::start(
         WsSession {
-            id: 0,
             hb: Instant::now(),
-            room: "Main".to_owned(),
-            addr: srv.get_ref().clone(),
+            state: SessionState::new(srv.get_ref().clone()),
         },
         &req,
         stream,
@@ -30,15 +29,10 @@ pub async fn ws_route(
 }
 
 struct WsSession {
-    /// unique session id
-    id: usize,
     /// Client must send ping at least once per 10 seconds (CLIENT_TIMEOUT),
     /// otherwise we drop connection.
     hb: Instant,
-    /// joined room
-    room: String,
-    /// Ws server
-    addr: Addr<server::WsServer>,
+    state: SessionState,
 }
 
 impl Actor for WsSession {
@@ -56,14 +50,15 @@ impl Actor for WsSession {
         // HttpContext::state() is instance of WsSessionState, state is shared
         // across all routes within application
         let addr = ctx.address();
-        self.addr
+        self.state
+            .addr
             .send(server::Connect {
                 addr: addr.recipient(),
             })
             .into_actor(self)
             .then(|res, act, ctx| {
                 match res {
-                    Ok(res) => act.id = res,
+                    Ok(res) => act.state.id = res,
                     // something is wrong with chat server
                     _ => ctx.stop(),
                 }
@@ -74,7 +69,9 @@ impl Actor for WsSession {
 
     fn stopping(&mut self, _: &mut Self::Context) -> Running {
         // notify chat server
-        self.addr.do_send(server::Disconnect { id: self.id });
+        self.state
+            .addr
+            .do_send(server::Disconnect { id: self.state.id });
         Running::Stop
     }
 }
@@ -91,7 +88,7 @@ impl Handler<ClientMsg> for WsSession {
             _msg => {
                 let as_str = serde_json::to_string(&_msg);
                 if let Ok(str) = as_str {
-                    log::trace!("~~> sending ctx.text {} = {}", self.id, str);
+                    log::trace!("~~> sending ctx.text {} = {}", self.state.id, str);
                     ctx.text(str);
                 } else {
                     log::error!("not a json message");
@@ -115,30 +112,22 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsSession {
         match msg {
             ws::Message::Ping(msg) => {
                 self.hb = Instant::now();
+                self.state
+                    .addr
+                    .do_send(server::Heartbeat { id: self.state.id });
                 ctx.pong(&msg);
             }
             ws::Message::Pong(_) => {
                 self.hb = Instant::now();
+                self.state
+                    .addr
+                    .do_send(server::Heartbeat { id: self.state.id });
             }
-            ws::Message::Text(text) => {
-                log::trace!(">>> incoming text from {} = {}", self.id, text);
-
-                match serde_json::from_str::<ClientMsg>(&text) {
-                    Ok(msg) => {
-                        log::trace!("✔ deserialized a clientMsg {:?}", msg);
-                        let outgoing = server::ClientBroadcastMessage {
-                            id: self.id,
-                            msg,
-                            room: self.room.clone(),
-                        };
-                        self.addr.do_send(outgoing)
-                    }
-                    Err(e) => {
-                        log::error!("could not deserialize an incoming message:");
-                        log::error!("{:?}", e);
-                    }
-                };
-            }
+            ws::Message::Text(text) => match self.state.incoming(&text) {
+                Incoming::Handled => (),
+                Incoming::Hello(hello) => self.hello(hello, ctx),
+                Incoming::Reply(msg) => ctx.notify(msg),
+            },
             ws::Message::Binary(_) => println!("Unexpected binary"),
             ws::Message::Close(reason) => {
                 ctx.close(reason);
@@ -153,6 +142,25 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsSession {
 }
 
 impl WsSession {
+    /// The client told us which page it's on - move it into the matching room
+    fn hello(&self, hello: server::Hello, ctx: &mut ws::WebsocketContext<Self>) {
+        self.state
+            .addr
+            .send(hello)
+            .into_actor(self)
+            .then(|res, act, _ctx| {
+                match res {
+                    Ok(room) => {
+                        log::trace!("{} joined room {}", act.state.id, room);
+                        act.state.room = room
+                    }
+                    Err(e) => log::error!("could not join a room {}", e),
+                }
+                fut::ready(())
+            })
+            .wait(ctx);
+    }
+
     /// helper method that sends ping to client every second.
     ///
     /// also this method checks heartbeats from client
@@ -164,7 +172,9 @@ impl WsSession {
                 println!("Websocket Client heartbeat failed, disconnecting!");
 
                 // notify chat server
-                act.addr.do_send(server::Disconnect { id: act.id });
+                act.state
+                    .addr
+                    .do_send(server::Disconnect { id: act.state.id });
 
                 // stop actor
                 ctx.stop();